@@ -0,0 +1,124 @@
+use crate::utils::distribution::Distribution2D;
+use crate::utils::image::Image;
+use crate::utils::util::{clamp, random_double};
+use crate::vec::vec3::Vec3;
+use std::f64::consts::PI;
+use std::io;
+
+// Shape of the opening light passes the lens through, which out of focus highlights
+// take on. Shapes lie within the unit circle around the lens center, scaled to the lens
+// radius by the camera.
+pub enum Aperture {
+    // Wide open lens
+    Circular,
+    // Regular polygon inscribed in the unit circle, formed by `blades` straight aperture
+    // blades, with one corner at `rotation` radians from the +x axis
+    Polygon {
+        blades: u32,
+        rotation: f64,
+    },
+    // Grayscale image stretched over the square around the unit circle, passing light
+    // where it is bright and inside the circle
+    Mask {
+        image: Image,
+        distribution: Distribution2D,
+    },
+}
+
+impl Aperture {
+    // `rotation` in degrees
+    pub fn polygon(blades: u32, rotation: f64) -> Aperture {
+        Aperture::Polygon {
+            blades: blades.max(3),
+            rotation: rotation.to_radians(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn mask(image: Image) -> Aperture {
+        let weights: Vec<f64> = image
+            .pixels
+            .iter()
+            .enumerate()
+            .map(|(i, pixel)| {
+                let x = 2.0 * ((i % image.width) as f64 + 0.5) / image.width as f64 - 1.0;
+                let y = 1.0 - 2.0 * ((i / image.width) as f64 + 0.5) / image.height as f64;
+                match x * x + y * y <= 1.0 {
+                    true => clamp(pixel.luminance(), 0.0, 1.0),
+                    false => 0.0,
+                }
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, image.width, image.height);
+        Aperture::Mask {
+            image,
+            distribution,
+        }
+    }
+
+    // Mask from a PPM image, its values taken as linear transmission
+    #[allow(dead_code)]
+    pub fn load_mask(path: &str) -> io::Result<Aperture> {
+        Ok(Aperture::mask(Image::load_ppm(path, false)?))
+    }
+
+    // Point on the aperture, picked in proportion to the light passing there
+    pub fn sample(&self) -> (f64, f64) {
+        match self {
+            Aperture::Circular => {
+                let p = Vec3::random_in_unit_disk();
+                (p.x(), p.y())
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // Uniform point in one of the triangles between the center and an edge
+                let n = f64::from(*blades);
+                let k = ((random_double() * n) as u32).min(blades - 1);
+                let corner = |i: u32| {
+                    let angle = rotation + 2.0 * PI * f64::from(i) / n;
+                    (angle.cos(), angle.sin())
+                };
+                let (a, b) = (corner(k), corner(k + 1));
+                let r = random_double().sqrt();
+                let s = random_double();
+                (
+                    r * ((1.0 - s) * a.0 + s * b.0),
+                    r * ((1.0 - s) * a.1 + s * b.1),
+                )
+            }
+            Aperture::Mask { distribution, .. } => {
+                let (u, v, _) = distribution.sample_continuous(random_double(), random_double());
+                (2.0 * u - 1.0, 1.0 - 2.0 * v)
+            }
+        }
+    }
+
+    // Fraction of the light passing the aperture at (x, y)
+    pub fn transmission(&self, x: f64, y: f64) -> f64 {
+        match self {
+            Aperture::Circular => match x * x + y * y <= 1.0 {
+                true => 1.0,
+                false => 0.0,
+            },
+            Aperture::Polygon { blades, rotation } => {
+                // Inside if within the apothem along the normal of the nearest edge
+                let n = f64::from(*blades);
+                let angle = y.atan2(x) - rotation;
+                let sector = (angle * n / (2.0 * PI)).floor();
+                let edge_normal = rotation + 2.0 * PI * (sector + 0.5) / n;
+                let distance = x * edge_normal.cos() + y * edge_normal.sin();
+                match distance <= (PI / n).cos() {
+                    true => 1.0,
+                    false => 0.0,
+                }
+            }
+            Aperture::Mask { image, .. } => {
+                if x * x + y * y > 1.0 {
+                    return 0.0;
+                }
+                let px = ((x + 1.0) / 2.0 * image.width as f64) as usize;
+                let py = ((1.0 - y) / 2.0 * image.height as f64) as usize;
+                clamp(image.pixel(px, py).luminance(), 0.0, 1.0)
+            }
+        }
+    }
+}
@@ -1,8 +1,44 @@
+use crate::camera::aperture::Aperture;
 use crate::utils::math_constants::degrees_to_radians;
+use crate::utils::util::{clamp, random_double};
 use crate::vec::vec3::{Point3, Ray, Vec3};
+use std::f64::consts::PI;
+use std::sync::Arc;
 
-#[derive(Clone, Copy)]
-pub struct Camera {
+// Turns image coordinates (s, t) in [0,1], with (0, 0) at the lower left corner,
+// into camera rays.
+pub trait Camera: Sync + Send {
+    fn generate_ray(&self, s: f64, t: f64) -> Ray;
+
+    // Whether (s, t) shows anything at all; points outside the image circle of a
+    // fisheye stay black
+    fn covers(&self, _s: f64, _t: f64) -> bool {
+        true
+    }
+
+    // Ray for (s, t) with the weight of the light it brings back, or None if no light
+    // reaches the image there
+    fn sample_ray(&self, s: f64, t: f64) -> Option<(Ray, f64)> {
+        match self.covers(s, t) {
+            true => Some((self.generate_ray(s, t), 1.0)),
+            false => None,
+        }
+    }
+}
+
+// Orthonormal camera frame, looking along -w with v pointing up
+pub(crate) fn camera_frame(lookfrom: Point3, lookat: Point3, vup: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = (lookfrom - lookat).unit();
+    let u = vup.cross(w).unit();
+    let v = w.cross(u);
+    (u, v, w)
+}
+
+// ----------------------------------------------------------------------
+// ----- PERSPECTIVE -----
+// ----------------------------------------------------------------------
+#[derive(Clone)]
+pub struct PerspectiveCamera {
     origin: Point3,
     lower_left_corner: Point3,
     horizontal: Vec3,
@@ -10,9 +46,13 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     lens_radius: f64,
+    aperture_shape: Arc<Aperture>,
+    // Plane of sharp focus, through `focus_point` facing the camera
+    focus_point: Point3,
+    focus_normal: Vec3,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn new(
         lookfrom: Point3,
         lookat: Point3,
@@ -21,15 +61,13 @@ impl Camera {
         aspect_ratio: f64,
         aperture: f64,
         focus_dist: f64,
-    ) -> Camera {
+    ) -> PerspectiveCamera {
         let theta = degrees_to_radians(vfov);
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
 
-        let w = (lookfrom - lookat).unit();
-        let u = vup.cross(w).unit();
-        let v = w.cross(u);
+        let (u, v, w) = camera_frame(lookfrom, lookat, vup);
 
         let origin = lookfrom;
         let horizontal = focus_dist * viewport_width * u;
@@ -38,7 +76,7 @@ impl Camera {
 
         let lens_radius = aperture / 2.0;
 
-        Camera {
+        PerspectiveCamera {
             origin,
             lower_left_corner,
             horizontal,
@@ -46,16 +84,809 @@ impl Camera {
             u,
             v,
             lens_radius,
+            aperture_shape: Arc::new(Aperture::Circular),
+            focus_point: origin - focus_dist * w,
+            focus_normal: w,
+        }
+    }
+
+    // Moves the image off the view axis by fractions of its width and height, like the
+    // rising front of a view camera. Looking level and shifting up keeps the verticals
+    // of buildings parallel.
+    #[allow(dead_code)]
+    pub fn with_shift(mut self, horizontal: f64, vertical: f64) -> PerspectiveCamera {
+        self.lower_left_corner =
+            self.lower_left_corner + horizontal * self.horizontal + vertical * self.vertical;
+        self
+    }
+
+    // Turns the plane of sharp focus about the horizontal (`tilt`) and vertical (`swing`)
+    // axes through the focus point, in degrees. Positive angles turn the top and the
+    // right side away from the camera; tilting it onto the ground keeps a whole table
+    // top sharp, tilting the other way gives the shallow focus band of miniatures.
+    #[allow(dead_code)]
+    pub fn with_tilt(mut self, tilt: f64, swing: f64) -> PerspectiveCamera {
+        let w = self.u.cross(self.v);
+        let tan = |angle: f64| degrees_to_radians(clamp(angle, -89.0, 89.0)).tan();
+        self.focus_normal = (w + tan(tilt) * self.v + tan(swing) * self.u).unit();
+        self
+    }
+
+    // Shapes the bokeh like the aperture of a real lens, `aperture` then being the
+    // diameter of the circle around it
+    #[allow(dead_code)]
+    pub fn with_aperture_shape(mut self, aperture_shape: Aperture) -> PerspectiveCamera {
+        self.aperture_shape = Arc::new(aperture_shape);
+        self
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn generate_ray(&self, s: f64, t: f64) -> Ray {
+        let (x, y) = self.aperture_shape.sample();
+        let offset: Vec3 = (self.lens_radius * x) * self.u + (self.lens_radius * y) * self.v;
+
+        // Rays through the lens center pass the focus plane at the point that is sharp.
+        // Where a strongly tilted plane never meets them, focus at infinity.
+        let chief = self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin;
+        let distance = (self.focus_point - self.origin).dot(&self.focus_normal)
+            / chief.dot(&self.focus_normal);
+        match distance > 0.0 && distance.is_finite() {
+            true => Ray::new(self.origin + offset, distance * chief - offset),
+            false => Ray::new(self.origin + offset, chief),
         }
     }
+}
+
+// ----------------------------------------------------------------------
+// ----- PHYSICAL -----
+// ----------------------------------------------------------------------
+// Diagonal of full frame 35 mm film, in meters
+const FULL_FRAME_DIAGONAL: f64 = 0.0433;
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd: Vec3 = self.lens_radius * Vec3::random_in_unit_disk();
-        let offset: Vec3 = rd.x() * self.u + rd.y() * self.v;
+// Film speed, shutter time and aperture as set on a real camera
+#[derive(Clone, Copy)]
+pub struct ExposureSettings {
+    // ISO film speed
+    pub(crate) iso: f64,
+    // Seconds the shutter stays open
+    pub(crate) shutter_time: f64,
+    // Focal length divided by the aperture diameter
+    pub(crate) f_number: f64,
+}
+
+impl ExposureSettings {
+    pub fn new(iso: f64, shutter_time: f64, f_number: f64) -> ExposureSettings {
+        ExposureSettings {
+            iso,
+            shutter_time,
+            f_number,
+        }
+    }
 
+    // Film exposure grows with ISO times shutter time over the aperture f-number squared.
+    // Given relative to the sunny 16 rule (f/16 at 1/ISO seconds), which leaves radiance
+    // as it is, so scenes lit like daylight come out right with daylight settings.
+    pub fn exposure(&self) -> f64 {
+        let sunny_16 = 1.0 / (16.0 * 16.0);
+        self.iso * self.shutter_time / (self.f_number * self.f_number) / sunny_16
+    }
+}
+
+// Thin lens camera on full frame film whose settings decide brightness, depth of field
+// and motion blur together, for matching photographs taken with known settings. Scene
+// units are meters.
+#[derive(Clone)]
+pub struct PhysicalCamera {
+    lens: PerspectiveCamera,
+    shutter_time: f64,
+    exposure: f64,
+}
+
+impl PhysicalCamera {
+    // `focal_length` in millimeters
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        focal_length: f64,
+        aspect_ratio: f64,
+        focus_dist: f64,
+        settings: ExposureSettings,
+    ) -> PhysicalCamera {
+        let focal_length = 0.001 * focal_length;
+        let film_height = FULL_FRAME_DIAGONAL / (1.0 + aspect_ratio * aspect_ratio).sqrt();
+        let vfov = 2.0 * (film_height / (2.0 * focal_length)).atan();
+        PhysicalCamera {
+            lens: PerspectiveCamera::new(
+                lookfrom,
+                lookat,
+                vup,
+                vfov.to_degrees(),
+                aspect_ratio,
+                focal_length / settings.f_number,
+                focus_dist,
+            ),
+            shutter_time: settings.shutter_time,
+            exposure: settings.exposure(),
+        }
+    }
+}
+
+impl Camera for PhysicalCamera {
+    // Rays are spread over the time the shutter is open
+    fn generate_ray(&self, s: f64, t: f64) -> Ray {
+        let ray = self.lens.generate_ray(s, t);
+        Ray::new_at(ray.origin, ray.dir, random_double() * self.shutter_time)
+    }
+
+    fn sample_ray(&self, s: f64, t: f64) -> Option<(Ray, f64)> {
+        Some((self.generate_ray(s, t), self.exposure))
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- ORTHOGRAPHIC -----
+// ----------------------------------------------------------------------
+// Parallel rays along the view direction, starting on the image plane. For technical
+// illustrations and isometric views.
+#[derive(Clone, Copy)]
+pub struct OrthographicCamera {
+    lower_left_corner: Point3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    direction: Vec3,
+}
+
+impl OrthographicCamera {
+    // Shows `view_height` world units vertically
+    #[allow(dead_code)]
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        view_height: f64,
+        aspect_ratio: f64,
+    ) -> OrthographicCamera {
+        let (u, v, w) = camera_frame(lookfrom, lookat, vup);
+
+        let horizontal = aspect_ratio * view_height * u;
+        let vertical = view_height * v;
+        let lower_left_corner = lookfrom - horizontal / 2.0 - vertical / 2.0;
+
+        OrthographicCamera {
+            lower_left_corner,
+            horizontal,
+            vertical,
+            direction: -w,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn generate_ray(&self, s: f64, t: f64) -> Ray {
         Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+            self.lower_left_corner + s * self.horizontal + t * self.vertical,
+            self.direction,
+        )
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- EQUIRECTANGULAR -----
+// ----------------------------------------------------------------------
+// Full 360 by 180 degree panorama, best rendered at an aspect ratio of 2:1. The image
+// center looks at `lookat`; the layout matches `EnvironmentMap`, so renders can be
+// used as environment maps directly.
+#[derive(Clone, Copy)]
+pub struct EquirectangularCamera {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl EquirectangularCamera {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3) -> EquirectangularCamera {
+        let (u, v, w) = camera_frame(lookfrom, lookat, vup);
+        EquirectangularCamera {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn generate_ray(&self, s: f64, t: f64) -> Ray {
+        let phi = 2.0 * PI * (s - 0.5);
+        let theta = PI * (1.0 - t);
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let direction = (sin_theta * phi.sin()) * self.u + cos_theta * self.v
+            - (sin_theta * phi.cos()) * self.w;
+        Ray::new(self.origin, direction)
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- FISHEYE -----
+// ----------------------------------------------------------------------
+// How the angle theta from the view direction maps to the distance r from the image
+// center
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub enum FisheyeMapping {
+    // r proportional to theta, as used for fulldome masters
+    Equidistant,
+    // r proportional to sin(theta / 2), preserving solid angles
+    Equisolid,
+}
+
+// Circular fisheye whose image circle touches the top and bottom of the image
+#[derive(Clone, Copy)]
+pub struct FisheyeCamera {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    // Half the field of view covered by the image circle, in radians
+    theta_max: f64,
+    aspect_ratio: f64,
+    mapping: FisheyeMapping,
+}
+
+impl FisheyeCamera {
+    // `fov` is the angle across the image circle in degrees, up to 360
+    #[allow(dead_code)]
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        fov: f64,
+        aspect_ratio: f64,
+        mapping: FisheyeMapping,
+    ) -> FisheyeCamera {
+        let (u, v, w) = camera_frame(lookfrom, lookat, vup);
+        FisheyeCamera {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            theta_max: degrees_to_radians(fov.min(360.0)) / 2.0,
+            aspect_ratio,
+            mapping,
+        }
+    }
+
+    // Position relative to the image circle, with radius one at its edge
+    fn circle_position(&self, s: f64, t: f64) -> (f64, f64) {
+        (self.aspect_ratio * (2.0 * s - 1.0), 2.0 * t - 1.0)
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn generate_ray(&self, s: f64, t: f64) -> Ray {
+        let (x, y) = self.circle_position(s, t);
+        let r = (x * x + y * y).sqrt().min(1.0);
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.theta_max,
+            FisheyeMapping::Equisolid => 2.0 * (r * (self.theta_max / 2.0).sin()).asin(),
+        };
+        let phi = y.atan2(x);
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let direction = (sin_theta * phi.cos()) * self.u + (sin_theta * phi.sin()) * self.v
+            - cos_theta * self.w;
+        Ray::new(self.origin, direction)
+    }
+
+    fn covers(&self, s: f64, t: f64) -> bool {
+        let (x, y) = self.circle_position(s, t);
+        x * x + y * y <= 1.0
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- CUBEMAP -----
+// ----------------------------------------------------------------------
+// The six 90 degree views around a point, side by side in the OpenGL face order
+// +x, -x, +y, -y, +z, -z and orientation, for reflection probes. Faces are aligned
+// with the world axes; render at an aspect ratio of 6:1.
+#[derive(Clone, Copy)]
+pub struct CubemapCamera {
+    origin: Point3,
+}
+
+impl CubemapCamera {
+    #[allow(dead_code)]
+    pub fn new(origin: Point3) -> CubemapCamera {
+        CubemapCamera { origin }
+    }
+}
+
+impl Camera for CubemapCamera {
+    fn generate_ray(&self, s: f64, t: f64) -> Ray {
+        let face = ((6.0 * s) as usize).min(5);
+        // Face coordinates in [-1,1], with tc running down the face
+        let sc = 2.0 * (6.0 * s - face as f64) - 1.0;
+        let tc = 1.0 - 2.0 * t;
+        let direction = match face {
+            0 => Vec3::new(1.0, -tc, -sc),
+            1 => Vec3::new(-1.0, -tc, sc),
+            2 => Vec3::new(sc, 1.0, tc),
+            3 => Vec3::new(sc, -1.0, -tc),
+            4 => Vec3::new(sc, -tc, 1.0),
+            _ => Vec3::new(-sc, -tc, -1.0),
+        };
+        Ray::new(self.origin, direction)
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- STEREO -----
+// ----------------------------------------------------------------------
+#[derive(Clone, Copy, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    // Side of the rig center the eye sits on, -1 for left and 1 for right
+    fn sign(&self) -> f64 {
+        match self {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        }
+    }
+}
+
+// How the two eye images share one output image
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub enum StereoLayout {
+    // Left eye in the top half, right eye in the bottom half
+    OverUnder,
+    // Left eye in the left half, right eye in the right half
+    SideBySide,
+}
+
+impl StereoLayout {
+    // Eye seen at (s, t) of the output image and the coordinates within its image
+    fn split(&self, s: f64, t: f64) -> (Eye, f64, f64) {
+        match self {
+            StereoLayout::OverUnder if t >= 0.5 => (Eye::Left, s, 2.0 * t - 1.0),
+            StereoLayout::OverUnder => (Eye::Right, s, 2.0 * t),
+            StereoLayout::SideBySide if s < 0.5 => (Eye::Left, 2.0 * s, t),
+            StereoLayout::SideBySide => (Eye::Right, 2.0 * s - 1.0, t),
+        }
+    }
+
+    // Aspect ratio of the output image holding two eye images with `eye_aspect_ratio`
+    pub fn image_aspect_ratio(&self, eye_aspect_ratio: f64) -> f64 {
+        match self {
+            StereoLayout::OverUnder => eye_aspect_ratio / 2.0,
+            StereoLayout::SideBySide => 2.0 * eye_aspect_ratio,
+        }
+    }
+}
+
+// How the eyes of a stereo pair are aimed
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub enum StereoConvergence {
+    // Both eyes look along the same direction, everything appears in front of the screen
+    Parallel,
+    // Both eyes are turned towards `lookat`, which then appears at screen depth
+    ToeIn,
+}
+
+// Renders the views of two cameras into one image, laid out for stereo viewers
+pub struct StereoCamera {
+    left: Arc<dyn Camera>,
+    right: Arc<dyn Camera>,
+    layout: StereoLayout,
+}
+
+impl StereoCamera {
+    pub fn new(
+        left: Arc<dyn Camera>,
+        right: Arc<dyn Camera>,
+        layout: StereoLayout,
+    ) -> StereoCamera {
+        StereoCamera {
+            left,
+            right,
+            layout,
+        }
+    }
+
+    // Pair of perspective cameras `interaxial` apart, centered on `lookfrom`. Field of
+    // view and aspect ratio are per eye.
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn perspective_pair(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        vfov: f64,
+        aspect_ratio: f64,
+        aperture: f64,
+        focus_dist: f64,
+        interaxial: f64,
+        convergence: StereoConvergence,
+        layout: StereoLayout,
+    ) -> StereoCamera {
+        let (u, _, _) = camera_frame(lookfrom, lookat, vup);
+        let eye_camera = |eye: Eye| -> Arc<dyn Camera> {
+            let offset = (0.5 * interaxial * eye.sign()) * u;
+            let eye_lookat = match convergence {
+                StereoConvergence::Parallel => lookat + offset,
+                StereoConvergence::ToeIn => lookat,
+            };
+            Arc::new(PerspectiveCamera::new(
+                lookfrom + offset,
+                eye_lookat,
+                vup,
+                vfov,
+                aspect_ratio,
+                aperture,
+                focus_dist,
+            ))
+        };
+        StereoCamera::new(eye_camera(Eye::Left), eye_camera(Eye::Right), layout)
+    }
+
+    // Omnidirectional stereo panorama for VR headsets, with eyes `interpupillary` apart
+    pub fn ods(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        interpupillary: f64,
+        layout: StereoLayout,
+    ) -> StereoCamera {
+        StereoCamera::new(
+            Arc::new(OdsCamera::new(
+                lookfrom,
+                lookat,
+                vup,
+                interpupillary,
+                Eye::Left,
+            )),
+            Arc::new(OdsCamera::new(
+                lookfrom,
+                lookat,
+                vup,
+                interpupillary,
+                Eye::Right,
+            )),
+            layout,
         )
     }
+
+    fn eye(&self, eye: Eye) -> &dyn Camera {
+        match eye {
+            Eye::Left => self.left.as_ref(),
+            Eye::Right => self.right.as_ref(),
+        }
+    }
+}
+
+impl Camera for StereoCamera {
+    fn generate_ray(&self, s: f64, t: f64) -> Ray {
+        let (eye, s, t) = self.layout.split(s, t);
+        self.eye(eye).generate_ray(s, t)
+    }
+
+    fn covers(&self, s: f64, t: f64) -> bool {
+        let (eye, s, t) = self.layout.split(s, t);
+        self.eye(eye).covers(s, t)
+    }
+
+    fn sample_ray(&self, s: f64, t: f64) -> Option<(Ray, f64)> {
+        let (eye, s, t) = self.layout.split(s, t);
+        self.eye(eye).sample_ray(s, t)
+    }
+}
+
+// One eye of an omnidirectional stereo panorama: an equirectangular image where every
+// column is seen from the eye position for that viewing direction, on a circle with
+// diameter `interpupillary` around `lookfrom`.
+#[derive(Clone, Copy)]
+pub struct OdsCamera {
+    panorama: EquirectangularCamera,
+    eye_offset: f64,
+}
+
+impl OdsCamera {
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        interpupillary: f64,
+        eye: Eye,
+    ) -> OdsCamera {
+        OdsCamera {
+            panorama: EquirectangularCamera::new(lookfrom, lookat, vup),
+            eye_offset: 0.5 * interpupillary * eye.sign(),
+        }
+    }
+}
+
+impl Camera for OdsCamera {
+    fn generate_ray(&self, s: f64, t: f64) -> Ray {
+        let panorama = &self.panorama;
+        // Offset the eye sideways, perpendicular to the horizontal viewing direction
+        let phi = 2.0 * PI * (s - 0.5);
+        let right = phi.cos() * panorama.u + phi.sin() * panorama.w;
+        let ray = panorama.generate_ray(s, t);
+        Ray::new(ray.origin + self.eye_offset * right, ray.dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_looks_along(camera: &dyn Camera, s: f64, t: f64, expected: Vec3) {
+        let direction = camera.generate_ray(s, t).dir.unit();
+        assert!(
+            (direction - expected).length() < 1e-9,
+            "({}, {}) looks along {:?}",
+            s,
+            t,
+            direction.e
+        );
+    }
+
+    #[test]
+    fn orthographic_rays_run_parallel_across_the_view() {
+        let camera = OrthographicCamera::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            2.0,
+            2.0,
+        );
+        let corners = [
+            (0.5, 0.5, Point3::new(0.0, 0.0, 5.0)),
+            (0.0, 0.0, Point3::new(-2.0, -1.0, 5.0)),
+            (1.0, 0.0, Point3::new(2.0, -1.0, 5.0)),
+            (1.0, 1.0, Point3::new(2.0, 1.0, 5.0)),
+        ];
+        for (s, t, origin) in corners.iter() {
+            let ray = camera.generate_ray(*s, *t);
+            assert!((ray.origin - *origin).length() < 1e-12);
+            assert!((ray.dir - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn equirectangular_centers_lookat_and_wraps_behind() {
+        let camera = EquirectangularCamera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        assert_looks_along(&camera, 0.5, 0.5, Vec3::new(1.0, 0.0, 0.0));
+        assert_looks_along(&camera, 0.75, 0.5, Vec3::new(0.0, 0.0, 1.0));
+        assert_looks_along(&camera, 0.25, 0.5, Vec3::new(0.0, 0.0, -1.0));
+        assert_looks_along(&camera, 0.0, 0.5, Vec3::new(-1.0, 0.0, 0.0));
+        assert_looks_along(&camera, 1.0, 0.5, Vec3::new(-1.0, 0.0, 0.0));
+        assert_looks_along(&camera, 0.3, 1.0, Vec3::new(0.0, 1.0, 0.0));
+        assert_looks_along(&camera, 0.3, 0.0, Vec3::new(0.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn fisheye_maps_the_circle_edge_to_half_the_field_of_view() {
+        let lookfrom = Point3::new(0.0, 0.0, 0.0);
+        let lookat = Point3::new(0.0, 0.0, -1.0);
+        let vup = Vec3::new(0.0, 1.0, 0.0);
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid].iter() {
+            let camera = FisheyeCamera::new(lookfrom, lookat, vup, 180.0, 2.0, *mapping);
+            assert_looks_along(&camera, 0.5, 0.5, Vec3::new(0.0, 0.0, -1.0));
+            assert_looks_along(&camera, 0.75, 0.5, Vec3::new(1.0, 0.0, 0.0));
+            assert_looks_along(&camera, 0.5, 1.0, Vec3::new(0.0, 1.0, 0.0));
+            assert_looks_along(&camera, 0.5, 0.0, Vec3::new(0.0, -1.0, 0.0));
+            assert!(camera.covers(0.5, 0.5));
+            assert!(camera.covers(0.5, 1.0));
+            assert!(!camera.covers(0.1, 0.5));
+            assert!(!camera.covers(0.7, 0.9));
+            assert!(camera.sample_ray(0.1, 0.5).is_none());
+        }
+
+        // Halfway to the edge is a quarter of the field of view from the center
+        let camera = FisheyeCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            180.0,
+            1.0,
+            FisheyeMapping::Equidistant,
+        );
+        let expected = Vec3::new(0.0, (PI / 4.0).sin(), -(PI / 4.0).cos());
+        assert_looks_along(&camera, 0.5, 0.75, expected);
+    }
+
+    #[test]
+    fn cubemap_faces_follow_the_opengl_layout() {
+        let camera = CubemapCamera::new(Point3::new(1.0, 2.0, 3.0));
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        // Forward, right and up of each face
+        let faces = [
+            (x, -z, y),
+            (-x, z, y),
+            (y, x, -z),
+            (-y, x, z),
+            (z, x, y),
+            (-z, -x, y),
+        ];
+        for (face, (forward, right, up)) in faces.iter().enumerate() {
+            let s = (face as f64 + 0.5) / 6.0;
+            assert_looks_along(&camera, s, 0.5, *forward);
+            assert_looks_along(
+                &camera,
+                s + 0.25 / 6.0,
+                0.5,
+                (*forward + 0.5 * *right).unit(),
+            );
+            assert_looks_along(&camera, s, 1.0, (*forward + *up).unit());
+            let ray = camera.generate_ray(s, 0.5);
+            assert!((ray.origin - Point3::new(1.0, 2.0, 3.0)).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn stereo_pair_puts_the_left_eye_left_of_the_right_one() {
+        let lookfrom = Point3::new(0.0, 0.0, 0.0);
+        let lookat = Point3::new(0.0, 0.0, -2.0);
+        let vup = Vec3::new(0.0, 1.0, 0.0);
+        let pair = |convergence, layout| {
+            StereoCamera::perspective_pair(
+                lookfrom,
+                lookat,
+                vup,
+                40.0,
+                1.0,
+                0.0,
+                2.0,
+                0.064,
+                convergence,
+                layout,
+            )
+        };
+
+        let parallel = pair(StereoConvergence::Parallel, StereoLayout::SideBySide);
+        let left = parallel.generate_ray(0.25, 0.5);
+        let right = parallel.generate_ray(0.75, 0.5);
+        assert!((left.origin - Point3::new(-0.032, 0.0, 0.0)).length() < 1e-12);
+        assert!((right.origin - Point3::new(0.032, 0.0, 0.0)).length() < 1e-12);
+        assert_looks_along(&parallel, 0.25, 0.5, Vec3::new(0.0, 0.0, -1.0));
+        assert_looks_along(&parallel, 0.75, 0.5, Vec3::new(0.0, 0.0, -1.0));
+
+        // Toed in eyes meet at `lookat`; over/under puts the left eye on top
+        let toe_in = pair(StereoConvergence::ToeIn, StereoLayout::OverUnder);
+        let left = toe_in.generate_ray(0.5, 0.75);
+        let right = toe_in.generate_ray(0.5, 0.25);
+        assert!((left.origin - Point3::new(-0.032, 0.0, 0.0)).length() < 1e-12);
+        assert!((right.origin - Point3::new(0.032, 0.0, 0.0)).length() < 1e-12);
+        assert_looks_along(&toe_in, 0.5, 0.75, (lookat - left.origin).unit());
+        assert_looks_along(&toe_in, 0.5, 0.25, (lookat - right.origin).unit());
+
+        assert_eq!(StereoLayout::SideBySide.image_aspect_ratio(1.5), 3.0);
+        assert_eq!(StereoLayout::OverUnder.image_aspect_ratio(1.5), 0.75);
+    }
+
+    #[test]
+    fn ods_eyes_sit_on_the_circle_across_the_view() {
+        let ipd = 0.064;
+        let camera = StereoCamera::ods(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            ipd,
+            StereoLayout::OverUnder,
+        );
+        // Left eye in the top half, looking forward and then to the right
+        let views = [
+            (0.5, Vec3::new(0.0, 0.0, -1.0), Vec3::new(1.0, 0.0, 0.0)),
+            (0.75, Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)),
+        ];
+        for (s, forward, right) in views.iter() {
+            let left = camera.generate_ray(*s, 0.75);
+            let right_eye = camera.generate_ray(*s, 0.25);
+            assert!((left.dir.unit() - *forward).length() < 1e-9);
+            assert!((right_eye.dir.unit() - *forward).length() < 1e-9);
+            assert!((left.origin + (0.5 * ipd) * *right).length() < 1e-12);
+            assert!((right_eye.origin - (0.5 * ipd) * *right).length() < 1e-12);
+        }
+
+        // The eyes stay level and ipd apart for every viewing direction
+        for i in 0..16 {
+            let s = (i as f64 + 0.5) / 16.0;
+            let left = camera.generate_ray(s, 0.6);
+            let right = camera.generate_ray(s, 0.1);
+            assert!(((left.origin - right.origin).length() - ipd).abs() < 1e-12);
+            assert!(left.origin.y().abs() < 1e-12);
+            assert!((left.origin - right.origin).dot(&left.dir).abs() < 1e-12);
+        }
+    }
+
+    fn level_camera(aperture: f64) -> PerspectiveCamera {
+        PerspectiveCamera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            1.0,
+            aperture,
+            1.0,
+        )
+    }
+
+    #[test]
+    fn shift_moves_the_image_off_the_view_axis() {
+        let camera = level_camera(0.0).with_shift(0.0, 0.25);
+        assert_looks_along(&camera, 0.5, 0.5, Vec3::new(0.0, 0.5, -1.0).unit());
+        assert_looks_along(&camera, 0.5, 0.25, Vec3::new(0.0, 0.0, -1.0));
+        let camera = level_camera(0.0).with_shift(-0.5, 0.0);
+        assert_looks_along(&camera, 1.0, 0.5, Vec3::new(0.0, 0.0, -1.0));
+        assert!(camera.generate_ray(0.3, 0.7).origin.length() < 1e-12);
+    }
+
+    #[test]
+    fn tilt_turns_the_plane_of_sharp_focus() {
+        let tilt = 30.0;
+        let normal = Vec3::new(0.0, degrees_to_radians(tilt).tan(), 1.0).unit();
+        let focus_point = Point3::new(0.0, 0.0, -1.0);
+        let on_focus_plane = |p: Point3| (p - focus_point).dot(&normal).abs() < 1e-9;
+
+        // Without aperture the ray ends where the image is sharp
+        let pinhole = level_camera(0.0).with_tilt(tilt, 0.0);
+        let sharp = |s: f64, t: f64| {
+            let ray = pinhole.generate_ray(s, t);
+            ray.origin + ray.dir
+        };
+        assert!((sharp(0.5, 0.5) - focus_point).length() < 1e-12);
+        for (s, t) in [(0.5, 1.0), (0.5, 0.0), (0.2, 0.8), (0.9, 0.3)].iter() {
+            assert!(on_focus_plane(sharp(*s, *t)));
+        }
+        // The top turns away from the camera, the bottom towards it
+        let tan = degrees_to_radians(tilt).tan();
+        assert!((sharp(0.5, 1.0).z() + 1.0 / (1.0 - tan)).abs() < 1e-9);
+        assert!((sharp(0.5, 0.0).z() + 1.0 / (1.0 + tan)).abs() < 1e-9);
+
+        // Rays through the whole lens meet on the same plane
+        let lens = level_camera(0.5).with_tilt(tilt, 0.0);
+        for _ in 0..16 {
+            let ray = lens.generate_ray(0.5, 1.0);
+            assert!((ray.origin + ray.dir - sharp(0.5, 1.0)).length() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn physical_camera_spreads_rays_over_the_open_shutter() {
+        let settings = ExposureSettings::new(100.0, 1.0 / 50.0, 16.0);
+        let camera = PhysicalCamera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            50.0,
+            1.5,
+            10.0,
+            settings,
+        );
+        let n = 1000;
+        let mut total = 0.0;
+        for _ in 0..n {
+            let (ray, weight) = camera.sample_ray(0.5, 0.5).unwrap();
+            assert!(ray.time >= 0.0 && ray.time < 1.0 / 50.0);
+            assert_eq!(weight, settings.exposure());
+            total += ray.time;
+        }
+        let mean = total / n as f64;
+        assert!((mean - 0.01).abs() < 0.001, "{}", mean);
+    }
 }
@@ -1 +1,3 @@
+pub mod aperture;
 pub mod camera;
+pub mod realistic_camera;
@@ -0,0 +1,634 @@
+use crate::camera::aperture::Aperture;
+use crate::camera::camera::{camera_frame, Camera};
+use crate::utils::util::random_double;
+use crate::vec::vec3::{Point3, Ray, Vec3};
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+// Scene units are meters, lens prescriptions are given in millimeters
+const MM: f64 = 0.001;
+// Rings of film positions the exit pupil is bounded for, from the center to the corners
+const EXIT_PUPIL_BINS: usize = 64;
+// Points on the rear element traced per ring when bounding the exit pupil
+const EXIT_PUPIL_SAMPLES: usize = 128 * 128;
+// Points on the exit pupil `generate_ray` tries before giving up on a film point
+const MAX_RAY_ATTEMPTS: usize = 64;
+
+// D-GAUSS F/2 22deg HFOV, US patent 2,673,491 (Tronnier), from Modern Lens Design p. 312,
+// scaled to a focal length of 50 mm
+const DOUBLE_GAUSS_50MM: &str = "
+# radius  thickness  ior    aperture
+29.475    3.76       1.67   25.2
+84.83     0.12       1      25.2
+19.275    4.025      1.67   23
+40.77     3.275      1.699  23
+12.75     5.705      1      18
+0         4.5        1      17.1
+-14.495   1.18       1.603  17
+40.77     6.065      1.658  20
+-20.385   0.19       1      20
+437.065   3.22       1.717  20
+-39.73    0          1      20
+";
+
+// One spherical interface of a lens, or the aperture stop when its radius is zero.
+// Prescriptions list elements from the front (scene side) to the rear of the lens.
+#[derive(Clone, Copy)]
+pub struct LensElement {
+    // Positive when the center of curvature lies towards the film
+    pub(crate) curvature_radius: f64,
+    // Distance along the axis to the next element, or to the film for the last one
+    pub(crate) thickness: f64,
+    // Index of refraction of the medium between this element and the next
+    pub(crate) ior: f64,
+    pub(crate) aperture_radius: f64,
+}
+
+impl LensElement {
+    // All lengths in millimeters, like the lens tables of pbrt and most optics books. An
+    // index of refraction of 0 stands for air.
+    pub fn new(
+        curvature_radius: f64,
+        thickness: f64,
+        ior: f64,
+        aperture_diameter: f64,
+    ) -> LensElement {
+        LensElement {
+            curvature_radius: curvature_radius * MM,
+            thickness: thickness * MM,
+            ior: match ior == 0.0 {
+                true => 1.0,
+                false => ior,
+            },
+            aperture_radius: aperture_diameter * MM / 2.0,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn load_prescription(path: &str) -> io::Result<Vec<LensElement>> {
+        LensElement::parse_prescription(&fs::read_to_string(path)?)
+    }
+
+    // Lens table with one element per line: curvature radius, thickness, index of
+    // refraction and aperture diameter. Lines starting with '#' are comments.
+    pub fn parse_prescription(text: &str) -> io::Result<Vec<LensElement>> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut elements = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values = line
+                .split_ascii_whitespace()
+                .map(|s| s.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|_| invalid("Invalid number in lens prescription"))?;
+            if values.len() != 4 {
+                return Err(invalid("Lens elements need four values"));
+            }
+            elements.push(LensElement::new(values[0], values[1], values[2], values[3]));
+        }
+        if elements.is_empty() {
+            return Err(invalid("Empty lens prescription"));
+        }
+        Ok(elements)
+    }
+
+    // Fast 50 mm double Gauss lens at f/2
+    pub fn double_gauss_50mm() -> Vec<LensElement> {
+        LensElement::parse_prescription(DOUBLE_GAUSS_50MM).unwrap()
+    }
+}
+
+// Region on the plane of the rear element that rays from a ring of film positions can
+// pass the lens through, for film positions on the +x axis
+#[derive(Clone, Copy)]
+struct PupilBounds {
+    min_x: f64,
+    min_y: f64,
+    max_x: f64,
+    max_y: f64,
+}
+
+impl PupilBounds {
+    fn area(&self) -> f64 {
+        match self.max_x > self.min_x && self.max_y > self.min_y {
+            true => (self.max_x - self.min_x) * (self.max_y - self.min_y),
+            false => 0.0,
+        }
+    }
+
+    fn lerp(&self, a: f64, b: f64) -> (f64, f64) {
+        (
+            self.min_x + a * (self.max_x - self.min_x),
+            self.min_y + b * (self.max_y - self.min_y),
+        )
+    }
+}
+
+// Camera that traces rays from the film through the elements of a real lens (after
+// pbrt's realistic camera), so vignetting, distortion and bokeh come from the lens
+// design itself. Ray weights are relative to the center of the film; the field of view
+// follows from the focal length of the lens and the film size.
+pub struct RealisticCamera {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    elements: Vec<LensElement>,
+    film_width: f64,
+    film_height: f64,
+    aperture_shape: Arc<Aperture>,
+    exit_pupils: Vec<PupilBounds>,
+    // Weighted area the lens is seen through from the center of the film
+    center_transmission: f64,
+}
+
+impl RealisticCamera {
+    // `film_diagonal` in millimeters, 43.3 for full frame 35 mm film. `focus_dist` is
+    // measured from the film, closer than the lens can focus gives its closest focus.
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        elements: Vec<LensElement>,
+        film_diagonal: f64,
+        aspect_ratio: f64,
+        focus_dist: f64,
+    ) -> RealisticCamera {
+        let (u, v, w) = camera_frame(lookfrom, lookat, vup);
+        let film_diagonal = film_diagonal * MM;
+        let film_height = film_diagonal / (1.0 + aspect_ratio * aspect_ratio).sqrt();
+
+        let mut camera = RealisticCamera {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            elements,
+            film_width: aspect_ratio * film_height,
+            film_height,
+            aperture_shape: Arc::new(Aperture::Circular),
+            exit_pupils: Vec::new(),
+            center_transmission: 0.0,
+        };
+        camera.focus(focus_dist);
+        camera.bound_exit_pupils();
+        camera
+    }
+
+    // Gives the aperture stop of the lens the shape of `aperture_shape`, scaled to the
+    // stop diameter of the prescription. The exit pupils are bounded for the round stop
+    // around any shape and stay as they are, only the center weight changes.
+    pub fn with_aperture_shape(mut self, aperture_shape: Aperture) -> RealisticCamera {
+        self.aperture_shape = Arc::new(aperture_shape);
+        self.center_transmission = self.transmission(&self.exit_pupils[0]);
+        self
+    }
+
+    fn bound_exit_pupils(&mut self) {
+        let film_diagonal = (sq(self.film_width) + sq(self.film_height)).sqrt();
+        self.exit_pupils = (0..EXIT_PUPIL_BINS)
+            .map(|i| {
+                let r0 = 0.5 * film_diagonal * i as f64 / EXIT_PUPIL_BINS as f64;
+                let r1 = 0.5 * film_diagonal * (i + 1) as f64 / EXIT_PUPIL_BINS as f64;
+                self.bound_exit_pupil(r0, r1)
+            })
+            .collect();
+        self.center_transmission = self.transmission(&self.exit_pupils[0]);
+    }
+
+    // In camera space, with the film at z = 0 and the lens along +z
+    fn rear_z(&self) -> f64 {
+        self.elements.last().unwrap().thickness
+    }
+
+    fn front_z(&self) -> f64 {
+        self.elements.iter().map(|e| e.thickness).sum()
+    }
+
+    fn rear_aperture_radius(&self) -> f64 {
+        self.elements.last().unwrap().aperture_radius
+    }
+
+    // Follows a camera space ray from the film out through the front of the lens, with
+    // `aperture` shaping the stop. Returns the ray and the fraction of light the stop
+    // lets through along it.
+    fn trace_from_film(&self, ray: Ray, aperture: &Aperture) -> Option<(Ray, f64)> {
+        let mut origin = ray.origin;
+        let mut dir = ray.dir.unit();
+        let mut transmission = 1.0;
+        let mut element_z = 0.0;
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            element_z += element.thickness;
+            let eta_t = match i {
+                0 => 1.0,
+                _ => self.elements[i - 1].ior,
+            };
+            let (o, d, t) = RealisticCamera::pass_element(
+                element,
+                element_z,
+                origin,
+                dir,
+                element.ior / eta_t,
+                aperture,
+            )?;
+            origin = o;
+            dir = d;
+            transmission *= t;
+        }
+        Some((Ray::new(origin, dir), transmission))
+    }
+
+    // Follows a camera space ray from the scene in through the lens towards the film
+    fn trace_from_scene(&self, ray: Ray) -> Option<Ray> {
+        let mut origin = ray.origin;
+        let mut dir = ray.dir.unit();
+        let mut element_z = self.front_z();
+        for (i, element) in self.elements.iter().enumerate() {
+            let eta_i = match i {
+                0 => 1.0,
+                _ => self.elements[i - 1].ior,
+            };
+            let (o, d, _) = RealisticCamera::pass_element(
+                element,
+                element_z,
+                origin,
+                dir,
+                eta_i / element.ior,
+                &self.aperture_shape,
+            )?;
+            origin = o;
+            dir = d;
+            element_z -= element.thickness;
+        }
+        Some(Ray::new(origin, dir))
+    }
+
+    // Intersects the element with its vertex at `element_z` and refracts through it,
+    // failing when the ray misses the aperture or is totally reflected. Also returns the
+    // transmission of the stop shaped by `aperture`, which is one for glass.
+    fn pass_element(
+        element: &LensElement,
+        element_z: f64,
+        origin: Point3,
+        dir: Vec3,
+        eta: f64,
+        aperture: &Aperture,
+    ) -> Option<(Point3, Vec3, f64)> {
+        if element.curvature_radius == 0.0 {
+            if dir.z().abs() < 1e-12 {
+                return None;
+            }
+            let t = (element_z - origin.z()) / dir.z();
+            let p = origin + t * dir;
+            let radius = element.aperture_radius;
+            let transmission = aperture.transmission(p.x() / radius, p.y() / radius);
+            return match t > 0.0 && transmission > 0.0 {
+                true => Some((p, dir, transmission)),
+                false => None,
+            };
+        }
+
+        let radius = element.curvature_radius;
+        let center = Point3::new(0.0, 0.0, element_z - radius);
+        let oc = origin - center;
+        let b = oc.dot(&dir);
+        let c = oc.length_squared() - radius * radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        // The lens surface is the cap of the sphere around the vertex
+        let root = discriminant.sqrt();
+        let use_closer = (dir.z() < 0.0) ^ (radius < 0.0);
+        let t = match use_closer {
+            true => -b - root,
+            false => -b + root,
+        };
+        if t < 0.0 {
+            return None;
+        }
+        let p = origin + t * dir;
+        if p.x() * p.x() + p.y() * p.y() > sq(element.aperture_radius) {
+            return None;
+        }
+
+        let mut n = (p - center).unit();
+        if n.dot(&dir) > 0.0 {
+            n = -n;
+        }
+        let cos_theta = -dir.dot(&n);
+        if eta * eta * (1.0 - cos_theta * cos_theta) >= 1.0 {
+            return None;
+        }
+        Some((p, Vec3::refract(&dir, &n, eta).unit(), 1.0))
+    }
+
+    // Moves the lens along its axis so objects `focus_dist` from the film are sharp,
+    // using the thick lens approximation
+    fn focus(&mut self, focus_dist: f64) {
+        // Trace rays parallel to the axis in from either side to find the focal points
+        // and principal planes
+        let height = 0.001 * self.rear_aperture_radius().max(1e-3);
+        let scene_ray = Ray::new(
+            Point3::new(height, 0.0, self.front_z() + 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+        let film_ray = Ray::new(
+            Point3::new(height, 0.0, self.rear_z() - 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        let (film_principal, film_focal) = match self.trace_from_scene(scene_ray) {
+            Some(ray) => cardinal_points(&scene_ray, &ray),
+            None => return,
+        };
+        let (scene_principal, _) = match self.trace_from_film(film_ray, &self.aperture_shape) {
+            Some((ray, _)) => cardinal_points(&film_ray, &ray),
+            None => return,
+        };
+        let focal_length = film_principal - film_focal;
+
+        // Image distance from the gaussian lens equation, where shifting the lens keeps
+        // the sum of object and image distance constant
+        let total = focus_dist - scene_principal + film_principal;
+        let image_distance =
+            0.5 * (total - (total * total - 4.0 * focal_length * total).max(0.0).sqrt());
+        let last = self.elements.len() - 1;
+        self.elements[last].thickness += image_distance - film_principal;
+    }
+
+    // Bounds the points on the rear element plane through which rays from film points
+    // between `r0` and `r1` on the +x axis leave the lens. Bounds are found for the round
+    // stop around the aperture shape, so they stay valid when rotated.
+    fn bound_exit_pupil(&self, r0: f64, r1: f64) -> PupilBounds {
+        let extent = 1.5 * self.rear_aperture_radius();
+        let grid = (EXIT_PUPIL_SAMPLES as f64).sqrt() as usize;
+        let mut bounds = PupilBounds {
+            min_x: f64::INFINITY,
+            min_y: f64::INFINITY,
+            max_x: -f64::INFINITY,
+            max_y: -f64::INFINITY,
+        };
+        for i in 0..grid * grid {
+            // Film positions independent of the grid row and column, so every part of the
+            // rear element is tried from across the whole ring
+            let film_x = r0 + (r1 - r0) * radical_inverse(i);
+            let x = extent * (2.0 * ((i % grid) as f64 + 0.5) / grid as f64 - 1.0);
+            let y = extent * (2.0 * ((i / grid) as f64 + 0.5) / grid as f64 - 1.0);
+            let film = Point3::new(film_x, 0.0, 0.0);
+            let rear = Point3::new(x, y, self.rear_z());
+            if self
+                .trace_from_film(Ray::new(film, rear - film), &Aperture::Circular)
+                .is_some()
+            {
+                bounds.min_x = bounds.min_x.min(x);
+                bounds.min_y = bounds.min_y.min(y);
+                bounds.max_x = bounds.max_x.max(x);
+                bounds.max_y = bounds.max_y.max(y);
+            }
+        }
+
+        // Samples only tell the pupil to the nearest grid cell
+        let margin = 2.0 * extent / grid as f64;
+        PupilBounds {
+            min_x: bounds.min_x - margin,
+            min_y: bounds.min_y - margin,
+            max_x: bounds.max_x + margin,
+            max_y: bounds.max_y + margin,
+        }
+    }
+
+    // Area of the pupil rays from the film center get through, weighted by cos^4 of
+    // their angle to the axis like the ray weights
+    fn transmission(&self, pupil: &PupilBounds) -> f64 {
+        let grid = (EXIT_PUPIL_SAMPLES as f64).sqrt() as usize;
+        let film = Point3::new(0.0, 0.0, 0.0);
+        let total: f64 = (0..grid * grid)
+            .map(|i| {
+                let (x, y) = pupil.lerp(
+                    ((i % grid) as f64 + 0.5) / grid as f64,
+                    ((i / grid) as f64 + 0.5) / grid as f64,
+                );
+                let dir = Point3::new(x, y, self.rear_z()) - film;
+                match self.trace_from_film(Ray::new(film, dir), &self.aperture_shape) {
+                    Some((_, transmission)) => transmission * dir.unit().z().powi(4),
+                    None => 0.0,
+                }
+            })
+            .sum();
+        pupil.area() * total / (grid * grid) as f64
+    }
+
+    // Camera space point on the film for image coordinates (s, t). The lens projects the
+    // image upside down and mirrored.
+    fn film_point(&self, s: f64, t: f64) -> Point3 {
+        Point3::new(
+            -(s - 0.5) * self.film_width,
+            -(t - 0.5) * self.film_height,
+            0.0,
+        )
+    }
+
+    fn exit_pupil(&self, film: &Point3) -> &PupilBounds {
+        let r = (film.x() * film.x() + film.y() * film.y()).sqrt();
+        let half_diagonal = 0.5 * (sq(self.film_width) + sq(self.film_height)).sqrt();
+        let bin = ((r / half_diagonal * EXIT_PUPIL_BINS as f64) as usize).min(EXIT_PUPIL_BINS - 1);
+        &self.exit_pupils[bin]
+    }
+
+    fn camera_to_world(&self, ray: &Ray) -> Ray {
+        let to_world = |v: &Vec3| v.x() * self.u + v.y() * self.v - v.z() * self.w;
+        Ray::new(self.origin + to_world(&ray.origin), to_world(&ray.dir))
+    }
+}
+
+fn sq(x: f64) -> f64 {
+    x * x
+}
+
+// Van der Corput sequence, `i` with its binary digits mirrored around the point
+fn radical_inverse(i: usize) -> f64 {
+    (i as u32).reverse_bits() as f64 / (1u64 << 32) as f64
+}
+
+// Axial positions of the principal plane and the focal point for a ray `ray_in`
+// parallel to the axis that leaves the lens as `ray_out`
+fn cardinal_points(ray_in: &Ray, ray_out: &Ray) -> (f64, f64) {
+    let t_focal = -ray_out.origin.x() / ray_out.dir.x();
+    let t_principal = (ray_in.origin.x() - ray_out.origin.x()) / ray_out.dir.x();
+    (ray_out.at(t_principal).z(), ray_out.at(t_focal).z())
+}
+
+impl Camera for RealisticCamera {
+    // First ray that makes it through the lens, without the vignetting weight. Where
+    // none does within a few tries, as outside `covers`, falls back to the ray from the
+    // film point through the center of the rear element, as if through a pinhole.
+    fn generate_ray(&self, s: f64, t: f64) -> Ray {
+        match (0..MAX_RAY_ATTEMPTS).find_map(|_| self.sample_ray(s, t)) {
+            Some((ray, _)) => ray,
+            None => {
+                let film = self.film_point(s, t);
+                let rear = Point3::new(0.0, 0.0, self.rear_z());
+                self.camera_to_world(&Ray::new(film, rear - film))
+            }
+        }
+    }
+
+    // Film points without an exit pupil only see the inside of the lens barrel
+    fn covers(&self, s: f64, t: f64) -> bool {
+        self.exit_pupil(&self.film_point(s, t)).area() > 0.0
+    }
+
+    fn sample_ray(&self, s: f64, t: f64) -> Option<(Ray, f64)> {
+        let film = self.film_point(s, t);
+        let pupil = self.exit_pupil(&film);
+        if pupil.area() <= 0.0 {
+            return None;
+        }
+        let r = (film.x() * film.x() + film.y() * film.y()).sqrt();
+
+        // Pupils are bounded for film points on the +x axis, rotate to this one
+        let (x, y) = pupil.lerp(random_double(), random_double());
+        let (sin_phi, cos_phi) = match r > 0.0 {
+            true => (film.y() / r, film.x() / r),
+            false => (0.0, 1.0),
+        };
+        let rear = Point3::new(
+            cos_phi * x - sin_phi * y,
+            sin_phi * x + cos_phi * y,
+            self.rear_z(),
+        );
+
+        let (ray, transmission) =
+            self.trace_from_film(Ray::new(film, rear - film), &self.aperture_shape)?;
+        let cos_theta = (rear - film).unit().z();
+        let weight = transmission * cos_theta.powi(4) * pupil.area() / self.center_transmission;
+        Some((self.camera_to_world(&ray), weight))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> String {
+        LensElement::parse_prescription(text)
+            .err()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn parses_double_gauss_in_meters() {
+        let elements = LensElement::double_gauss_50mm();
+        assert_eq!(elements.len(), 11);
+        let front = elements[0];
+        assert!((front.curvature_radius - 0.029475).abs() < 1e-12);
+        assert!((front.thickness - 0.00376).abs() < 1e-12);
+        assert!((front.aperture_radius - 0.0126).abs() < 1e-12);
+        assert_eq!(front.ior, 1.67);
+        let stop = elements[5];
+        assert_eq!(stop.curvature_radius, 0.0);
+        assert!((stop.aperture_radius - 0.00855).abs() < 1e-12);
+        assert_eq!(elements[10].thickness, 0.0);
+    }
+
+    #[test]
+    fn treats_zero_ior_as_air() {
+        let elements = LensElement::parse_prescription(
+            "# stop only\n\n  0  4.5  0  17.1\n12.75 5.705 1.5 18\n",
+        )
+        .unwrap();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0].ior, 1.0);
+        assert_eq!(elements[1].ior, 1.5);
+    }
+
+    #[test]
+    fn rejects_malformed_prescriptions() {
+        assert_eq!(
+            parse_error("29.475 3.76 glass 25.2"),
+            "Invalid number in lens prescription"
+        );
+        assert_eq!(
+            parse_error("29.475 3.76 1.67"),
+            "Lens elements need four values"
+        );
+        assert_eq!(
+            parse_error("29.475 3.76 1.67 25.2 1"),
+            "Lens elements need four values"
+        );
+        assert_eq!(
+            parse_error("# radius thickness ior aperture\n\n"),
+            "Empty lens prescription"
+        );
+    }
+
+    #[test]
+    fn gives_up_where_no_ray_gets_through() {
+        // Film far larger than the image circle of the lens
+        let camera = RealisticCamera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            LensElement::double_gauss_50mm(),
+            400.0,
+            1.5,
+            10.0,
+        );
+        assert!(camera.covers(0.5, 0.5));
+        assert!(!camera.covers(1.0, 1.0));
+        assert!(camera.sample_ray(1.0, 1.0).is_none());
+
+        // The fallback looks through the center of the lens, up and to the right
+        let ray = camera.generate_ray(1.0, 1.0);
+        let dir = ray.dir.unit();
+        assert!(dir.x() > 0.0 && dir.y() > 0.0 && dir.z() < 0.0);
+        assert!((ray.dir - camera.generate_ray(1.0, 1.0).dir).length() < 1e-12);
+    }
+
+    #[test]
+    fn radical_inverse_mirrors_binary_digits() {
+        let expected = [0.0, 0.5, 0.25, 0.75, 0.125, 0.625, 0.375, 0.875];
+        for (i, x) in expected.iter().enumerate() {
+            assert_eq!(radical_inverse(i), *x);
+        }
+    }
+
+    #[test]
+    fn aperture_shape_keeps_the_exit_pupils() {
+        let round = RealisticCamera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            LensElement::double_gauss_50mm(),
+            43.3,
+            1.5,
+            10.0,
+        );
+        let pupils: Vec<(f64, f64, f64, f64)> = round
+            .exit_pupils
+            .iter()
+            .map(|p| (p.min_x, p.min_y, p.max_x, p.max_y))
+            .collect();
+        let round_transmission = round.center_transmission;
+
+        // A square inscribed in the round stop passes 2 / pi of its light
+        let square = round.with_aperture_shape(Aperture::polygon(4, 45.0));
+        for (pupil, bounds) in square.exit_pupils.iter().zip(pupils.iter()) {
+            assert_eq!(
+                (pupil.min_x, pupil.min_y, pupil.max_x, pupil.max_y),
+                *bounds
+            );
+        }
+        let ratio = square.center_transmission / round_transmission;
+        assert!(
+            (ratio - 2.0 / std::f64::consts::PI).abs() < 0.05,
+            "{}",
+            ratio
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use crate::utils::util::clamp;
+use crate::vec::vec3::Vec3;
+use std::fs;
+use std::io;
+
+// Candela distribution of a luminaire from an IES LM-63 photometric file (type C
+// photometry), normalized to a peak of one.
+pub struct IesProfile {
+    // Peak candela of the file, including its candela multiplier
+    pub(crate) peak_intensity: f64,
+    // Vertical angles in degrees, 0 pointing straight down the luminaire axis
+    pub(crate) vertical_angles: Vec<f64>,
+    // Horizontal angles in degrees around the axis
+    pub(crate) horizontal_angles: Vec<f64>,
+    // One row of values over the vertical angles for every horizontal angle
+    pub(crate) candela: Vec<Vec<f64>>,
+}
+
+impl IesProfile {
+    #[allow(dead_code)]
+    pub fn load(path: &str) -> io::Result<IesProfile> {
+        IesProfile::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> io::Result<IesProfile> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        // Keywords and comments up to the TILT line are of no interest
+        let mut lines = text.lines();
+        let tilt = loop {
+            match lines.next() {
+                Some(line) if line.trim_start().starts_with("TILT=") => {
+                    break line.trim_start()["TILT=".len()..].trim().to_string()
+                }
+                Some(_) => continue,
+                None => return Err(invalid("Missing TILT line in IES file")),
+            }
+        };
+        let rest: Vec<&str> = lines.collect();
+        let mut numbers = rest.iter().flat_map(|line| {
+            line.split(|c: char| c.is_ascii_whitespace() || c == ',')
+                .filter(|s| !s.is_empty())
+        });
+        let mut next = || -> io::Result<f64> {
+            numbers
+                .next()
+                .ok_or_else(|| invalid("Truncated IES data"))?
+                .parse::<f64>()
+                .map_err(|_| invalid("Invalid number in IES data"))
+        };
+
+        // Lamp tilt data describes how the lamp output changes with its mounting
+        // angle, ignored here
+        if tilt == "INCLUDE" {
+            let _geometry = next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()? as u32;
+        // Units, width, length, height, ballast factor, ballast lamp factor and watts
+        for _ in 0..7 {
+            next()?;
+        }
+        if photometric_type != 1 {
+            return Err(invalid("Only type C IES photometry is supported"));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(invalid("IES file without angles"));
+        }
+
+        let vertical_angles = (0..vertical_count)
+            .map(|_| next())
+            .collect::<io::Result<Vec<f64>>>()?;
+        let horizontal_angles = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<io::Result<Vec<f64>>>()?;
+        let mut candela = (0..horizontal_count)
+            .map(|_| {
+                (0..vertical_count)
+                    .map(|_| next())
+                    .collect::<io::Result<Vec<f64>>>()
+            })
+            .collect::<io::Result<Vec<Vec<f64>>>>()?;
+
+        let peak = candela.iter().flatten().fold(0.0f64, |a, b| a.max(*b));
+        if peak > 0.0 {
+            for value in candela.iter_mut().flatten() {
+                *value /= peak;
+            }
+        }
+
+        Ok(IesProfile {
+            peak_intensity: multiplier * peak,
+            vertical_angles,
+            horizontal_angles,
+            candela,
+        })
+    }
+
+    // Luminous intensity in candela towards `w`, in the frame of `value`
+    pub fn intensity(&self, w: &Vec3) -> f64 {
+        self.peak_intensity * self.value(w)
+    }
+
+    // Relative intensity towards `w`, given in the luminaire's frame with +z along
+    // its axis (vertical angle 0) and +x at horizontal angle 0
+    pub fn value(&self, w: &Vec3) -> f64 {
+        let w = w.unit();
+        let theta = clamp(w.z(), -1.0, 1.0).acos().to_degrees();
+        let mut phi = w.y().atan2(w.x()).to_degrees();
+        if phi < 0.0 {
+            phi += 360.0;
+        }
+
+        // Files only list the horizontal angles up to the luminaire's symmetry
+        let first = self.horizontal_angles[0];
+        let last = *self.horizontal_angles.last().unwrap();
+        if last <= 0.0 {
+            phi = 0.0;
+        } else if first == 90.0 && last == 270.0 {
+            // Symmetric about the 90-270 degree plane
+            if !(90.0..=270.0).contains(&phi) {
+                phi = (180.0 - phi).rem_euclid(360.0);
+            }
+        } else if last <= 90.0 {
+            phi = match phi {
+                p if p > 270.0 => 360.0 - p,
+                p if p > 180.0 => p - 180.0,
+                p if p > 90.0 => 180.0 - p,
+                p => p,
+            };
+        } else if last <= 180.0 && phi > 180.0 {
+            phi = 360.0 - phi;
+        }
+
+        let first_vertical = self.vertical_angles[0];
+        let last_vertical = *self.vertical_angles.last().unwrap();
+        if theta < first_vertical || theta > last_vertical {
+            return 0.0;
+        }
+
+        // Full listings run up to the last angle short of 360, which blends back into 0
+        let full_circle = last > 180.0 && first == 0.0;
+        let (h0, h1, th) = match full_circle && phi > last {
+            true => (
+                self.horizontal_angles.len() - 1,
+                0,
+                (phi - last) / (360.0 - last),
+            ),
+            false => IesProfile::bracket(&self.horizontal_angles, phi),
+        };
+        let (v0, v1, tv) = IesProfile::bracket(&self.vertical_angles, theta);
+        let lerp = |row: &Vec<f64>| (1.0 - tv) * row[v0] + tv * row[v1];
+        (1.0 - th) * lerp(&self.candela[h0]) + th * lerp(&self.candela[h1])
+    }
+
+    // Neighbouring indices around `x` in the sorted `angles` and the blend between them
+    fn bracket(angles: &[f64], x: f64) -> (usize, usize, f64) {
+        let upper = angles.partition_point(|a| *a <= x);
+        if upper == 0 {
+            return (0, 0, 0.0);
+        }
+        if upper >= angles.len() {
+            let last = angles.len() - 1;
+            return (last, last, 0.0);
+        }
+        let lower = upper - 1;
+        let t = (x - angles[lower]) / (angles[upper] - angles[lower]);
+        (lower, upper, t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = "IESNA:LM-63-2002
+[TEST] Downlight
+[MANUFAC] Nobody
+TILT=NONE
+1 1000 1 3 2 1 1 0.1 0.1 0
+1 1 20
+0 45 90
+0 90
+200 100 0
+400, 200, 100
+";
+
+    fn parse_error(text: &str) -> String {
+        IesProfile::parse(text).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn parses_type_c_profile() {
+        let profile = IesProfile::parse(PROFILE).unwrap();
+        assert_eq!(profile.vertical_angles, vec![0.0, 45.0, 90.0]);
+        assert_eq!(profile.horizontal_angles, vec![0.0, 90.0]);
+        assert_eq!(
+            profile.candela,
+            vec![vec![0.5, 0.25, 0.0], vec![1.0, 0.5, 0.25]]
+        );
+    }
+
+    #[test]
+    fn skips_included_tilt_data() {
+        let text = PROFILE.replace("TILT=NONE", "TILT=INCLUDE\n1\n2\n0 90\n1 0.9");
+        let profile = IesProfile::parse(&text).unwrap();
+        assert_eq!(profile.vertical_angles, vec![0.0, 45.0, 90.0]);
+        assert_eq!(profile.candela[1][0], 1.0);
+    }
+
+    #[test]
+    fn interpolates_between_angles() {
+        let profile = IesProfile::parse(PROFILE).unwrap();
+        assert!((profile.value(&Vec3::new(0.0, 0.0, 1.0)) - 0.5).abs() < 1e-9);
+        assert!((profile.value(&Vec3::new(0.0, 1.0, 1.0)) - 0.5).abs() < 1e-9);
+        assert!((profile.value(&Vec3::new(1.0, 1.0, 0.0)) - 0.125).abs() < 1e-9);
+        // Quadrant symmetry mirrors the listed 0-90 degree range
+        assert!((profile.value(&Vec3::new(-1.0, -1.0, 0.0)) - 0.125).abs() < 1e-9);
+        assert_eq!(profile.value(&Vec3::new(0.0, 0.0, -1.0)), 0.0);
+    }
+
+    #[test]
+    fn mirrors_bilateral_symmetry_about_90_270_plane() {
+        let text = PROFILE
+            .replace("3 2 1 1", "3 3 1 1")
+            .replace("0 90\n200", "90 180 270\n200")
+            .replace("400, 200, 100", "400 200 100\n300 150 50");
+        let profile = IesProfile::parse(&text).unwrap();
+        let sideways = |x: f64, y: f64| profile.value(&Vec3::new(x, y, 0.0));
+        // 0 degrees mirrors to 180, 300 degrees to 240
+        assert!((sideways(1.0, 0.0) - sideways(-1.0, 0.0)).abs() < 1e-9);
+        assert!((sideways(1.0, 0.0) - 0.25).abs() < 1e-9);
+        let (sin, cos) = 300f64.to_radians().sin_cos();
+        assert!((sideways(cos, sin) - sideways(-cos, sin)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_malformed_profiles() {
+        assert_eq!(
+            parse_error("IESNA:LM-63-2002\n1 1000 1"),
+            "Missing TILT line in IES file"
+        );
+        assert_eq!(
+            parse_error(&PROFILE.replace("400, 200, 100\n", "")),
+            "Truncated IES data"
+        );
+        assert_eq!(
+            parse_error(&PROFILE.replace("1 1 20", "1 1 twenty")),
+            "Invalid number in IES data"
+        );
+        assert_eq!(
+            parse_error(&PROFILE.replace("3 2 1 1", "3 2 2 1")),
+            "Only type C IES photometry is supported"
+        );
+        assert_eq!(
+            parse_error(&PROFILE.replace("3 2 1 1", "0 2 1 1")),
+            "IES file without angles"
+        );
+    }
+
+    #[test]
+    fn keeps_the_absolute_peak_intensity() {
+        let profile = IesProfile::parse(PROFILE).unwrap();
+        assert_eq!(profile.peak_intensity, 400.0);
+        assert!((profile.intensity(&Vec3::new(0.0, 0.0, 1.0)) - 200.0).abs() < 1e-9);
+
+        // The candela multiplier scales every value in the file
+        let doubled = IesProfile::parse(&PROFILE.replace("1 1000 1 3", "1 1000 2 3")).unwrap();
+        assert_eq!(doubled.peak_intensity, 800.0);
+        assert_eq!(doubled.candela, profile.candela);
+    }
+
+    #[test]
+    fn wraps_full_listings_around_to_zero_degrees() {
+        let text = PROFILE
+            .replace("3 2 1 1", "3 4 1 1")
+            .replace("0 90\n200", "0 90 180 270\n200")
+            .replace("400, 200, 100", "400 200 100\n300 150 50\n100 50 25");
+        let profile = IesProfile::parse(&text).unwrap();
+        let sideways = |degrees: f64| {
+            let (sin, cos) = degrees.to_radians().sin_cos();
+            profile.value(&Vec3::new(cos, sin, 0.0))
+        };
+        // Rows at 0 and 270 degrees hold 0 and 25 (normalized 0.0625) sideways
+        assert!((sideways(270.0) - 0.0625).abs() < 1e-9);
+        assert!((sideways(315.0) - 0.03125).abs() < 1e-9);
+        assert!(sideways(359.0) < sideways(315.0));
+    }
+}
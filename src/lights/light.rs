@@ -0,0 +1,421 @@
+use crate::lights::ies::IesProfile;
+use crate::lights::light_bvh::LightBounds;
+use crate::utils::util::{clamp, random_double};
+use crate::vec::onb::Onb;
+use crate::vec::vec3::{Color, Point3, Vec3};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// Incident light arriving at a shading point from a sampled light
+pub struct LightSample {
+    // Unit direction from the shading point towards the light
+    pub(crate) wi: Vec3,
+    // Distance to the light, infinite for lights at infinity
+    pub(crate) distance: f64,
+    // Incident radiance, already divided by the sampling density
+    pub(crate) radiance: Color,
+    // Solid angle density of `wi`, None for lights that rays can never hit by chance
+    pub(crate) pdf: Option<f64>,
+}
+
+// Analytic light that can only be reached by explicitly sampling it, since rays
+// never hit it by chance.
+pub trait Light: Sync + Send {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample>;
+
+    // Solid angle density of `sample_li` at `p` returning the direction `wi`
+    fn pdf_li(&self, _p: &Point3, _wi: &Vec3) -> f64 {
+        0.0
+    }
+
+    // Extent and power of the emitted light, None for lights at infinity
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
+    // Radiance seen by rays leaving the scene in direction `dir`, for lights at
+    // infinity that are large enough to be seen
+    fn le(&self, _dir: &Vec3) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- POINT -----
+// ----------------------------------------------------------------------
+pub struct PointLight {
+    pub(crate) position: Point3,
+    // Radiant intensity, the power per solid angle
+    pub(crate) intensity: Color,
+    // Photometric profile hanging straight down, with horizontal angle 0 along +x
+    pub(crate) profile: Option<Arc<IesProfile>>,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+            profile: None,
+        }
+    }
+
+    // Shapes the light by the profile's candela values, which `intensity` then scales
+    // from candela to scene units
+    #[allow(dead_code)]
+    pub fn with_profile(mut self, profile: Arc<IesProfile>) -> PointLight {
+        self.profile = Some(profile);
+        self
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        let wi = to_light / distance;
+
+        let profile = match &self.profile {
+            Some(profile) => profile.intensity(&Vec3::new(-wi.x(), -wi.z(), wi.y())),
+            None => 1.0,
+        };
+        if profile <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: (profile / distance_squared) * self.intensity,
+            pdf: None,
+        })
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let peak = self.profile.as_ref().map_or(1.0, |p| p.peak_intensity);
+        Some(LightBounds::new(
+            self.position,
+            self.position,
+            4.0 * PI * peak * self.intensity.luminance(),
+            Vec3::new(0.0, 0.0, 1.0),
+            -1.0,
+            0.0,
+            false,
+        ))
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- SPOT -----
+// ----------------------------------------------------------------------
+pub struct SpotLight {
+    pub(crate) position: Point3,
+    // Unit direction the spot light points at
+    pub(crate) direction: Vec3,
+    pub(crate) intensity: Color,
+    pub(crate) cos_falloff_start: f64,
+    pub(crate) cos_total_width: f64,
+    // Photometric profile with vertical angle 0 along `direction`
+    pub(crate) profile: Option<Arc<IesProfile>>,
+    // Frame of the profile, with `w` along `direction` and `u` at horizontal angle 0
+    pub(crate) profile_frame: Onb,
+}
+
+impl SpotLight {
+    // Full intensity up to `falloff_start` degrees off the axis, smoothly fading to
+    // zero at `total_width` degrees.
+    pub fn new(
+        position: Point3,
+        target: Point3,
+        intensity: Color,
+        falloff_start: f64,
+        total_width: f64,
+    ) -> SpotLight {
+        let direction = (target - position).unit();
+        SpotLight {
+            position,
+            direction,
+            intensity,
+            cos_falloff_start: falloff_start.to_radians().cos(),
+            cos_total_width: total_width.to_radians().cos(),
+            profile: None,
+            profile_frame: Onb::build_from_w(&direction),
+        }
+    }
+
+    // Shapes the light by the profile's candela values on top of the cone falloff, with
+    // `intensity` scaling them from candela to scene units. Horizontal angle 0 of the
+    // profile points along `horizontal_zero`, projected across the spot direction.
+    #[allow(dead_code)]
+    pub fn with_profile(mut self, profile: Arc<IesProfile>, horizontal_zero: Vec3) -> SpotLight {
+        self.profile = Some(profile);
+        self.profile_frame = Onb::build_from_w_and_u(&self.direction, &horizontal_zero);
+        self
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if self.cos_falloff_start <= self.cos_total_width {
+            return match cos_theta >= self.cos_total_width {
+                true => 1.0,
+                false => 0.0,
+            };
+        }
+        let t = clamp(
+            (cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width),
+            0.0,
+            1.0,
+        );
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        let wi = to_light / distance;
+
+        let mut falloff = self.falloff(-wi.dot(&self.direction));
+        if let Some(profile) = &self.profile {
+            falloff *= profile.intensity(&self.profile_frame.world_to_local(&-wi));
+        }
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: (falloff / distance_squared) * self.intensity,
+            pdf: None,
+        })
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let theta_e = self.cos_total_width.acos() - self.cos_falloff_start.acos();
+        let peak = self.profile.as_ref().map_or(1.0, |p| p.peak_intensity);
+        Some(LightBounds::new(
+            self.position,
+            self.position,
+            4.0 * PI * peak * self.intensity.luminance(),
+            self.direction,
+            self.cos_falloff_start,
+            theta_e.cos(),
+            false,
+        ))
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- DIRECTIONAL -----
+// ----------------------------------------------------------------------
+// Light from infinitely far away, like the sun
+pub struct DirectionalLight {
+    // Unit direction the light travels in
+    pub(crate) direction: Vec3,
+    // Irradiance on a surface perpendicular to the light
+    pub(crate) irradiance: Color,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> DirectionalLight {
+        DirectionalLight {
+            direction: direction.unit(),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _p: &Point3) -> Option<LightSample> {
+        Some(LightSample {
+            wi: -self.direction,
+            distance: f64::INFINITY,
+            radiance: self.irradiance,
+            pdf: None,
+        })
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- SUN -----
+// ----------------------------------------------------------------------
+// Angular radius of the solar disk as seen from the earth
+const SUN_ANGULAR_RADIUS: f64 = 0.0047;
+
+// Sun disk at infinity, colored by the atmosphere it shines through. Matches the
+// sun of a `PreethamSky` with the same direction and turbidity.
+pub struct SunLight {
+    // Unit direction towards the center of the sun
+    pub(crate) direction: Vec3,
+    pub(crate) radiance: Color,
+    pub(crate) cos_max: f64,
+}
+
+impl SunLight {
+    // `irradiance` is what a surface facing the sun would receive outside the atmosphere
+    pub fn new(direction: Vec3, turbidity: f64, irradiance: f64) -> SunLight {
+        let direction = direction.unit();
+        let cos_max = SUN_ANGULAR_RADIUS.cos();
+        let solid_angle = 2.0 * PI * (1.0 - cos_max);
+        let transmittance = SunLight::transmittance(direction.y(), turbidity);
+        SunLight {
+            direction,
+            radiance: (irradiance / solid_angle) * transmittance,
+            cos_max,
+        }
+    }
+
+    // Transmittance of the atmosphere for sun light (Preetham et al. 1999, appendix),
+    // from Rayleigh and aerosol scattering only; absorption by ozone and water vapor is
+    // left out. Evaluated at roughly 650nm, 550nm and 450nm.
+    fn transmittance(cos_theta_s: f64, turbidity: f64) -> Color {
+        if cos_theta_s <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        // Relative optical mass of the air the light travels through
+        let theta_degrees = cos_theta_s.acos().to_degrees();
+        let m = 1.0 / (cos_theta_s + 0.15 * (93.885 - theta_degrees).powf(-1.253));
+        let beta = 0.04608365822050 * turbidity - 0.04586025928522;
+        let tau = |lambda: f64| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * m).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * m).exp();
+            rayleigh * aerosol
+        };
+        Color::new(tau(0.65), tau(0.55), tau(0.45))
+    }
+}
+
+impl Light for SunLight {
+    fn sample_li(&self, _p: &Point3) -> Option<LightSample> {
+        if self.direction.y() <= 0.0 {
+            return None;
+        }
+        // Uniform direction inside the cone of the disk
+        let cos_theta = 1.0 - random_double() * (1.0 - self.cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * PI * random_double();
+        let frame = Onb::build_from_w(&self.direction);
+        let wi = frame.local_to_world(&Vec3::new(
+            phi.cos() * sin_theta,
+            phi.sin() * sin_theta,
+            cos_theta,
+        ));
+        let solid_angle = 2.0 * PI * (1.0 - self.cos_max);
+        Some(LightSample {
+            wi,
+            distance: f64::INFINITY,
+            radiance: solid_angle * self.radiance,
+            // Rays escaping towards the sun only see it after specular bounces
+            pdf: None,
+        })
+    }
+
+    fn le(&self, dir: &Vec3) -> Color {
+        match dir.unit().dot(&self.direction) >= self.cos_max {
+            true => self.radiance,
+            false => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_light_falls_off_with_the_squared_distance() {
+        let light = PointLight::new(Point3::new(0.0, 4.0, 0.0), Color::new(8.0, 4.0, 2.0));
+        let near = light.sample_li(&Point3::new(0.0, 2.0, 0.0)).unwrap();
+        let far = light.sample_li(&Point3::new(0.0, 0.0, 0.0)).unwrap();
+        assert!((near.wi - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+        assert!((near.distance - 2.0).abs() < 1e-12);
+        assert!((near.radiance.r() - 2.0).abs() < 1e-12);
+        assert!((far.radiance.r() - 0.5).abs() < 1e-12);
+        assert!(near.pdf.is_none());
+    }
+
+    #[test]
+    fn spot_light_fades_out_across_the_cone_edge() {
+        let position = Point3::new(0.0, 1.0, 0.0);
+        let light = SpotLight::new(
+            position,
+            Point3::new(0.0, 0.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            20.0,
+            40.0,
+        );
+        let at_angle = |degrees: f64| {
+            let p = Point3::new(degrees.to_radians().tan(), 0.0, 0.0);
+            light
+                .sample_li(&p)
+                .map(|sample| sample.radiance.r() * sample.distance * sample.distance)
+        };
+        assert!((at_angle(0.0).unwrap() - 1.0).abs() < 1e-12);
+        assert!((at_angle(15.0).unwrap() - 1.0).abs() < 1e-12);
+        let edge = at_angle(30.0).unwrap();
+        assert!(edge > 0.0 && edge < 1.0);
+        assert!(at_angle(45.0).is_none());
+    }
+
+    #[test]
+    fn directional_light_is_the_same_everywhere() {
+        let light = DirectionalLight::new(Vec3::new(0.0, -2.0, 0.0), Color::new(3.0, 3.0, 3.0));
+        for p in &[Point3::new(0.0, 0.0, 0.0), Point3::new(100.0, -5.0, 3.0)] {
+            let sample = light.sample_li(p).unwrap();
+            assert!((sample.wi - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+            assert!(sample.distance.is_infinite());
+            assert!((sample.radiance.g() - 3.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn low_sun_is_dimmer_and_redder() {
+        let high = SunLight::new(Vec3::new(0.0, 1.0, 0.0), 3.0, 1000.0);
+        let low = SunLight::new(Vec3::new(1.0, 0.1, 0.0), 3.0, 1000.0);
+        assert!(low.radiance.luminance() < high.radiance.luminance());
+        assert!(low.radiance.b() / low.radiance.r() < high.radiance.b() / high.radiance.r());
+
+        // Below the horizon the sun lights nothing
+        let set = SunLight::new(Vec3::new(1.0, -0.1, 0.0), 3.0, 1000.0);
+        assert!(set.sample_li(&Point3::new(0.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn spot_profile_turns_with_its_horizontal_reference() {
+        // Sideways brightest at horizontal angle 0, dark at 180
+        let profile = IesProfile::parse(
+            "TILT=NONE
+1 1000 1 2 4 1 1 0 0 0
+1 1 20
+0 90
+0 90 180 270
+400 400
+400 200
+400 0
+400 100
+",
+        )
+        .unwrap();
+        let light = SpotLight::new(
+            Point3::new(0.0, 1.0, 0.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            80.0,
+            80.0,
+        )
+        .with_profile(Arc::new(profile), Vec3::new(0.0, 0.0, 1.0));
+        let candela = |p: Point3| {
+            let sample = light.sample_li(&p).unwrap();
+            sample.radiance.r() * sample.distance * sample.distance
+        };
+        assert!((candela(Point3::new(0.0, 0.0, 0.0)) - 400.0).abs() < 1e-9);
+
+        // 45 degrees off the axis towards the reference, away from it and to either side,
+        // where 90 degrees lies along the spot direction crossed with the reference
+        assert!((candela(Point3::new(0.0, 0.0, 1.0)) - 400.0).abs() < 1e-9);
+        assert!((candela(Point3::new(0.0, 0.0, -1.0)) - 200.0).abs() < 1e-9);
+        assert!((candela(Point3::new(-1.0, 0.0, 0.0)) - 300.0).abs() < 1e-9);
+        assert!((candela(Point3::new(1.0, 0.0, 0.0)) - 250.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,399 @@
+use crate::lights::light::Light;
+use crate::utils::util::{clamp, random_double};
+use crate::vec::vec3::{Point3, Vec3};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// Spatial and directional extent of the light emitted by one or more lights, used
+// to estimate how much they may contribute at a shading point (Conty Estevez and
+// Kulla 2018, as in pbrt-v4).
+#[derive(Clone, Copy)]
+pub struct LightBounds {
+    pub(crate) min: Point3,
+    pub(crate) max: Point3,
+    // Total emitted power, as luminance
+    pub(crate) phi: f64,
+    // Cone of surface normals (or spot directions) around `axis` with half angle theta_o
+    pub(crate) axis: Vec3,
+    pub(crate) cos_theta_o: f64,
+    // Emission spreads up to theta_e beyond the normal cone
+    pub(crate) cos_theta_e: f64,
+    pub(crate) two_sided: bool,
+}
+
+impl LightBounds {
+    pub fn new(
+        min: Point3,
+        max: Point3,
+        phi: f64,
+        axis: Vec3,
+        cos_theta_o: f64,
+        cos_theta_e: f64,
+        two_sided: bool,
+    ) -> LightBounds {
+        LightBounds {
+            min,
+            max,
+            phi,
+            axis: axis.unit(),
+            cos_theta_o,
+            cos_theta_e,
+            two_sided,
+        }
+    }
+
+    fn centroid(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    fn union(&self, other: &LightBounds) -> LightBounds {
+        let (axis, cos_theta_o) =
+            union_cones(&self.axis, self.cos_theta_o, &other.axis, other.cos_theta_o);
+        LightBounds {
+            min: Vec3::new(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+                self.min.z().min(other.min.z()),
+            ),
+            max: Vec3::new(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+                self.max.z().max(other.max.z()),
+            ),
+            phi: self.phi + other.phi,
+            axis,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    // Conservative estimate of the light arriving at `p` on a surface with normal `n`
+    pub fn importance(&self, p: &Point3, n: &Vec3) -> f64 {
+        // Clamp the distance to the size of the bounds, so points inside do not blow up
+        let pc = self.centroid();
+        let diagonal = (self.max - self.min).length();
+        let distance_squared = (*p - pc).length_squared().max(0.5 * diagonal);
+
+        // Angle between the emission axis and the direction to `p`
+        let wi = (*p - pc).unit();
+        let mut cos_theta_w = self.axis.dot(&wi);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = safe_sqrt(1.0 - cos_theta_w * cos_theta_w);
+
+        // Angle the bounds subtend as seen from `p`
+        let radius_squared = (self.max - pc).length_squared();
+        let cos_theta_b = match (*p - pc).length_squared() < radius_squared {
+            true => -1.0,
+            false => safe_sqrt(1.0 - radius_squared / (*p - pc).length_squared()),
+        };
+        let sin_theta_b = safe_sqrt(1.0 - cos_theta_b * cos_theta_b);
+
+        // Smallest possible angle to the emission cone: theta_w - theta_o - theta_b
+        let sin_theta_o = safe_sqrt(1.0 - self.cos_theta_o * self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+        let mut importance = self.phi * cos_theta_p / distance_squared;
+
+        // Smallest possible angle to the surface normal
+        if n.length_squared() > 0.0 {
+            let cos_theta_i = wi.dot(n).abs();
+            let sin_theta_i = safe_sqrt(1.0 - cos_theta_i * cos_theta_i);
+            importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+        importance.max(0.0)
+    }
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+// cos(max(0, a - b)) and sin(max(0, a - b)) from the sines and cosines of a and b
+fn cos_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    match cos_a > cos_b {
+        true => 1.0,
+        false => cos_a * cos_b + sin_a * sin_b,
+    }
+}
+
+fn sin_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    match cos_a > cos_b {
+        true => 0.0,
+        false => sin_a * cos_b - cos_a * sin_b,
+    }
+}
+
+// Smallest cone containing both cones, given by their axes and cos(half angle)
+fn union_cones(a: &Vec3, cos_a: f64, b: &Vec3, cos_b: f64) -> (Vec3, f64) {
+    let theta_a = clamp(cos_a, -1.0, 1.0).acos();
+    let theta_b = clamp(cos_b, -1.0, 1.0).acos();
+    let theta_d = clamp(a.dot(b), -1.0, 1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (*a, cos_a);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return (*b, cos_b);
+    }
+
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    let rotation_axis = a.cross(*b);
+    if theta_o >= PI || rotation_axis.length_squared() == 0.0 {
+        return (*a, -1.0);
+    }
+
+    // Rotate a towards b so the new cone just touches the far sides of both
+    let theta_r = theta_o - theta_a;
+    let k = rotation_axis.unit();
+    let (sin_r, cos_r) = theta_r.sin_cos();
+    let axis = cos_r * *a + sin_r * k.cross(*a) + ((1.0 - cos_r) * k.dot(a)) * k;
+    (axis.unit(), theta_o.cos())
+}
+
+enum LightNode {
+    Leaf {
+        light: usize,
+        bounds: LightBounds,
+    },
+    Interior {
+        bounds: LightBounds,
+        children: Box<[LightNode; 2]>,
+    },
+}
+
+impl LightNode {
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            LightNode::Leaf { bounds, .. } => bounds,
+            LightNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+// Hierarchy over all lights with bounds, for picking one light at a shading point
+// with probability proportional to its estimated contribution. Lights without
+// bounds, such as the sun, are left to the caller to sample every time.
+pub struct LightBvh {
+    root: Option<LightNode>,
+    unbounded: Vec<usize>,
+    // Path from the root to every bounded light, one bit per level with 1 for the
+    // second child
+    trails: Vec<Option<(u64, u32)>>,
+}
+
+impl LightBvh {
+    pub fn new(lights: &[Arc<dyn Light>]) -> LightBvh {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for (index, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) if bounds.phi > 0.0 => bounded.push((index, bounds)),
+                Some(_) => {}
+                None => unbounded.push(index),
+            }
+        }
+
+        let mut trails = vec![None; lights.len()];
+        let root = match bounded.is_empty() {
+            true => None,
+            false => Some(LightBvh::build(&mut bounded, 0, 0, &mut trails)),
+        };
+        LightBvh {
+            root,
+            unbounded,
+            trails,
+        }
+    }
+
+    // Splits the lights at the median of their centroids along the widest axis
+    fn build(
+        lights: &mut [(usize, LightBounds)],
+        trail: u64,
+        depth: u32,
+        trails: &mut Vec<Option<(u64, u32)>>,
+    ) -> LightNode {
+        if lights.len() == 1 {
+            let (light, bounds) = lights[0];
+            trails[light] = Some((trail, depth));
+            return LightNode::Leaf { light, bounds };
+        }
+
+        let centroid = |bounds: &LightBounds, axis: usize| bounds.centroid().e[axis];
+        let extent = |axis: usize| {
+            let values = lights.iter().map(|(_, bounds)| centroid(bounds, axis));
+            let min = values.clone().fold(f64::INFINITY, f64::min);
+            let max = values.fold(-f64::INFINITY, f64::max);
+            max - min
+        };
+        let axis = (0..3)
+            .max_by(|a, b| extent(*a).partial_cmp(&extent(*b)).unwrap())
+            .unwrap();
+        lights.sort_by(|a, b| {
+            centroid(&a.1, axis)
+                .partial_cmp(&centroid(&b.1, axis))
+                .unwrap()
+        });
+
+        let (left, right) = lights.split_at_mut(lights.len() / 2);
+        let first = LightBvh::build(left, trail, depth + 1, trails);
+        let second = LightBvh::build(right, trail | (1 << depth), depth + 1, trails);
+        LightNode::Interior {
+            bounds: first.bounds().union(second.bounds()),
+            children: Box::new([first, second]),
+        }
+    }
+
+    pub fn unbounded(&self) -> &[usize] {
+        &self.unbounded
+    }
+
+    // Picks a bounded light for the shading point `p` with normal `n`, returning its
+    // index and the probability of having picked it
+    pub fn sample(&self, p: &Point3, n: &Vec3) -> Option<(usize, f64)> {
+        let mut node = self.root.as_ref()?;
+        let mut pmf = 1.0;
+        loop {
+            match node {
+                LightNode::Leaf { light, bounds } => {
+                    return match bounds.importance(p, n) > 0.0 {
+                        true => Some((*light, pmf)),
+                        false => None,
+                    };
+                }
+                LightNode::Interior { children, .. } => {
+                    let first = children[0].bounds().importance(p, n);
+                    let second = children[1].bounds().importance(p, n);
+                    if first + second <= 0.0 {
+                        return None;
+                    }
+                    let first_probability = first / (first + second);
+                    node = match random_double() < first_probability {
+                        true => {
+                            pmf *= first_probability;
+                            &children[0]
+                        }
+                        false => {
+                            pmf *= 1.0 - first_probability;
+                            &children[1]
+                        }
+                    };
+                }
+            }
+        }
+    }
+
+    // Probability that `sample` picks `light` at the shading point `p` with normal `n`
+    pub fn pmf(&self, p: &Point3, n: &Vec3, light: usize) -> f64 {
+        let (trail, depth) = match self.trails.get(light) {
+            Some(Some(trail)) => *trail,
+            _ => return 0.0,
+        };
+        let mut node = match &self.root {
+            Some(root) => root,
+            None => return 0.0,
+        };
+        let mut pmf = 1.0;
+        for level in 0..depth {
+            if let LightNode::Interior { children, .. } = node {
+                let first = children[0].bounds().importance(p, n);
+                let second = children[1].bounds().importance(p, n);
+                if first + second <= 0.0 {
+                    return 0.0;
+                }
+                let child = ((trail >> level) & 1) as usize;
+                pmf *= [first, second][child] / (first + second);
+                node = &children[child];
+            }
+        }
+        match node.bounds().importance(p, n) > 0.0 {
+            true => pmf,
+            false => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lights::light::{PointLight, SpotLight, SunLight};
+    use crate::vec::vec3::Color;
+
+    fn lights() -> Vec<Arc<dyn Light>> {
+        let point = |x: f64, y: f64, z: f64, power: f64| -> Arc<dyn Light> {
+            Arc::new(PointLight::new(
+                Point3::new(x, y, z),
+                Color::new(power, power, power),
+            ))
+        };
+        vec![
+            point(0.0, 4.0, 0.0, 10.0),
+            point(3.0, 2.0, -1.0, 2.0),
+            Arc::new(SunLight::new(Vec3::new(0.0, 1.0, 0.0), 3.0, 1.0)),
+            point(-5.0, 1.0, 2.0, 30.0),
+            point(-4.0, 6.0, -3.0, 1.0),
+            Arc::new(SpotLight::new(
+                Point3::new(1.0, 8.0, 1.0),
+                Point3::new(0.0, 0.0, 0.0),
+                Color::new(50.0, 50.0, 50.0),
+                30.0,
+                60.0,
+            )),
+            point(6.0, 0.5, 5.0, 5.0),
+        ]
+    }
+
+    // Shading points lit by every light, with normals facing up at an angle
+    fn shading_points() -> Vec<(Point3, Vec3)> {
+        vec![
+            (Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+            (Point3::new(1.0, 0.2, -1.0), Vec3::new(0.3, 1.0, 0.1).unit()),
+            (
+                Point3::new(-2.0, 0.0, 1.5),
+                Vec3::new(-0.2, 1.0, 0.4).unit(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn pmf_sums_to_one_over_the_bounded_lights() {
+        let lights = lights();
+        let bvh = LightBvh::new(&lights);
+        assert_eq!(bvh.unbounded(), &[2]);
+        for (p, n) in shading_points() {
+            let total: f64 = (0..lights.len()).map(|light| bvh.pmf(&p, &n, light)).sum();
+            assert!((total - 1.0).abs() < 1e-9, "{}", total);
+            assert_eq!(bvh.pmf(&p, &n, 2), 0.0);
+        }
+    }
+
+    #[test]
+    fn pmf_matches_the_sampling_frequency() {
+        let lights = lights();
+        let bvh = LightBvh::new(&lights);
+        for (p, n) in shading_points() {
+            let samples = 100_000;
+            let mut frequency = vec![0.0; lights.len()];
+            for _ in 0..samples {
+                let (light, pmf) = bvh.sample(&p, &n).unwrap();
+                assert!((pmf - bvh.pmf(&p, &n, light)).abs() < 1e-12);
+                frequency[light] += 1.0 / samples as f64;
+            }
+            for (light, frequency) in frequency.iter().enumerate() {
+                let pmf = bvh.pmf(&p, &n, light);
+                assert!(
+                    (frequency - pmf).abs() < 0.01,
+                    "{} {} {}",
+                    light,
+                    frequency,
+                    pmf
+                );
+            }
+        }
+    }
+}
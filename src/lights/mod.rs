@@ -0,0 +1,3 @@
+pub mod ies;
+pub mod light;
+pub mod light_bvh;
@@ -1,12 +1,13 @@
 use crate::scene::scene::Scene;
 
 mod camera;
+mod lights;
 mod objects;
 mod utils;
 mod vec;
 mod scene;
 
 fn main() {
-    let scene = Scene::setup_complex_scene();
+    let mut scene = Scene::setup_complex_scene();
     scene.multithreadet_rendering()
 }
@@ -1,13 +1,22 @@
 use crate::objects::material::Material;
+use crate::vec::onb::Onb;
 use crate::vec::vec3::{Point3, Ray, Vec3};
 use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub(crate) p: Point3,
     pub(crate) normal: Vec3,
     pub(crate) material: Arc<dyn Material>,
     pub(crate) t: f64,
+    pub(crate) u: f64,
+    pub(crate) v: f64,
     pub(crate) front_face: bool,
+    // Surface tangents, the partial derivatives of p with respect to u and v
+    pub(crate) dpdu: Vec3,
+    pub(crate) dpdv: Vec3,
+    // Index of the scene light this surface belongs to, if it is one
+    pub(crate) light: Option<usize>,
 }
 
 impl HitRecord {
@@ -16,6 +25,8 @@ impl HitRecord {
         normal: Vec3,
         material: Arc<dyn Material>,
         t: f64,
+        u: f64,
+        v: f64,
         front_face: bool,
     ) -> HitRecord {
         HitRecord {
@@ -23,10 +34,26 @@ impl HitRecord {
             normal,
             material,
             t,
+            u,
+            v,
             front_face,
+            dpdu: Vec3::default(),
+            dpdv: Vec3::default(),
+            light: None,
         }
     }
 
+    pub fn set_tangents(&mut self, dpdu: Vec3, dpdv: Vec3) {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+    }
+
+    // Local shading frame around the normal, with u following dp/du where available
+    // so anisotropic materials line up with the surface parameterization.
+    pub fn shading_frame(&self) -> Onb {
+        Onb::build_from_w_and_u(&self.normal, &self.dpdu)
+    }
+
     pub fn set_face_normal(&mut self, ray: Ray, outward_normal: Vec3) {
         self.front_face = ray.dir.dot(&outward_normal) < 0.0;
         self.normal = match self.front_face {
@@ -1,9 +1,74 @@
 use crate::objects::hittable::HitRecord;
-use crate::utils::util::random_double;
-use crate::vec::vec3::{Color, Ray, Vec3};
+use crate::objects::medium::Medium;
+use crate::objects::microfacet::{
+    cos_theta, fresnel_complex_color, fresnel_dielectric, fresnel_schlick, reflect, refract,
+    roughness_to_alpha, schlick_weight, transmission_half_vector, TrowbridgeReitz,
+};
+use crate::objects::texture::{SolidColor, Texture};
+use crate::objects::thin_film::ThinFilm;
+use crate::utils::complex::Complex;
+use crate::utils::math_constants::degrees_to_radians;
+use crate::utils::util::{clamp, hash_double, random_double};
+use crate::vec::vec3::{Color, Point3, Ray, Vec3};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// Outcome of a material scattering an incoming ray
+pub struct ScatterRecord {
+    pub(crate) scattered: Ray,
+    pub(crate) attenuation: Color,
+    // Picked from a discrete (specular) lobe, which explicit light sampling can never produce
+    pub(crate) is_specular: bool,
+}
+
+impl ScatterRecord {
+    pub fn new(scattered: Ray, attenuation: Color) -> ScatterRecord {
+        ScatterRecord {
+            scattered,
+            attenuation,
+            is_specular: false,
+        }
+    }
+
+    pub fn specular(scattered: Ray, attenuation: Color) -> ScatterRecord {
+        ScatterRecord {
+            scattered,
+            attenuation,
+            is_specular: true,
+        }
+    }
+}
 
 pub trait Material: Sync + Send {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Vec3)>;
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord>;
+
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // Solid angle density with which `scatter` picks `scattered`.
+    // Zero for materials that only scatter into discrete (specular) directions.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    // BSDF times the cosine of `scattered` with the normal, used to connect to lights
+    // directly. Zero for materials that only scatter into discrete (specular) directions.
+    fn eval_bsdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // Medium filling the inside of objects with this material. Rays refracted
+    // through a front face at `rec` travel through it until they leave through a back face.
+    fn interior_medium(&self, _rec: &HitRecord) -> Option<&Medium> {
+        None
+    }
+
+    // Whether the surface is transparent at the hit, in which case hittables should
+    // ignore the intersection and report the next one instead.
+    fn is_cutout(&self, _rec: &HitRecord) -> bool {
+        false
+    }
 }
 
 // ----------------------------------------------------------------------
@@ -21,11 +86,147 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let reflected = Vec3::reflect(&r_in.dir.unit(), &rec.normal);
-        let scattered = Ray::new(rec.p, reflected + self.fuzz * Vec3::random_in_unit_sphere());
+        let scattered = Ray::new_at(
+            rec.p,
+            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
+            r_in.time,
+        );
         let attenuation = self.albedo;
-        Some((scattered, attenuation))
+        Some(ScatterRecord::specular(scattered, attenuation))
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- CONDUCTOR -----
+// ----------------------------------------------------------------------
+// Microfacet metal with a GGX distribution, Smith masking-shadowing and
+// Fresnel reflectance computed from the complex index of refraction `eta + i*k`.
+pub struct Conductor {
+    pub(crate) eta: Color,
+    pub(crate) k: Color,
+    pub(crate) distribution: TrowbridgeReitz,
+    pub(crate) thin_film: Option<ThinFilm>,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness_u: f64, roughness_v: f64) -> Conductor {
+        Conductor {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::new(
+                roughness_to_alpha(roughness_u),
+                roughness_to_alpha(roughness_v),
+            ),
+            thin_film: None,
+        }
+    }
+
+    pub fn with_thin_film(mut self, thin_film: ThinFilm) -> Conductor {
+        self.thin_film = Some(thin_film);
+        self
+    }
+
+    fn fresnel(&self, cos_theta_i: f64) -> Color {
+        match self.thin_film {
+            None => fresnel_complex_color(cos_theta_i, &self.eta, &self.k),
+            Some(thin_film) => {
+                let substrate = [
+                    Complex::new(self.eta.r(), self.k.r()),
+                    Complex::new(self.eta.g(), self.k.g()),
+                    Complex::new(self.eta.b(), self.k.b()),
+                ];
+                thin_film.reflectance(cos_theta_i, 1.0, &substrate)
+            }
+        }
+    }
+
+    // RGB approximations of measured spectral data (roughly 650nm, 550nm, 450nm)
+    pub fn gold(roughness: f64) -> Conductor {
+        Conductor::new(
+            Color::new(0.143, 0.374, 1.442),
+            Color::new(3.983, 2.385, 1.603),
+            roughness,
+            roughness,
+        )
+    }
+
+    #[allow(dead_code)]
+    pub fn copper(roughness: f64) -> Conductor {
+        Conductor::new(
+            Color::new(0.200, 0.924, 1.102),
+            Color::new(3.912, 2.452, 2.142),
+            roughness,
+            roughness,
+        )
+    }
+
+    #[allow(dead_code)]
+    pub fn aluminium(roughness: f64) -> Conductor {
+        Conductor::new(
+            Color::new(1.657, 0.880, 0.521),
+            Color::new(9.224, 6.270, 4.837),
+            roughness,
+            roughness,
+        )
+    }
+
+    #[allow(dead_code)]
+    pub fn silver(roughness: f64) -> Conductor {
+        Conductor::new(
+            Color::new(0.155, 0.117, 0.138),
+            Color::new(4.828, 3.122, 2.147),
+            roughness,
+            roughness,
+        )
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        if cos_theta(&wo) <= 0.0 {
+            return None;
+        }
+
+        let wm = self.distribution.sample_wm(&wo);
+        let wi = reflect(&wo, &wm);
+        if cos_theta(&wi) <= 0.0 {
+            return None;
+        }
+
+        // Sampling visible normals cancels D and one G1 out of f * cos / pdf
+        let fresnel = self.fresnel(wo.dot(&wm));
+        let attenuation =
+            self.distribution.g(&wo, &wi) / self.distribution.g1(&wo) * fresnel;
+        let scattered = Ray::new_at(rec.p, frame.local_to_world(&wi), r_in.time);
+        Some(ScatterRecord::new(scattered, attenuation))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let wi = frame.world_to_local(&scattered.dir.unit());
+        if cos_theta(&wo) <= 0.0 || cos_theta(&wi) <= 0.0 {
+            return 0.0;
+        }
+        let wh = (wo + wi).unit();
+        self.distribution.visible_d(&wo, &wh) / (4.0 * wo.dot(&wh).abs())
+    }
+
+    fn eval_bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let wi = frame.world_to_local(&scattered.dir.unit());
+        if cos_theta(&wo) <= 0.0 || cos_theta(&wi) <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let wh = (wo + wi).unit();
+        let specular =
+            self.distribution.d(&wh) * self.distribution.g(&wo, &wi) / (4.0 * cos_theta(&wo));
+        specular * self.fresnel(wo.dot(&wh))
     }
 }
 
@@ -43,11 +244,101 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let scatter_direction = rec.normal + Vec3::random_unit_vector();
-        let scattered = Ray::new(rec.p, scatter_direction);
+        let scattered = Ray::new_at(rec.p, scatter_direction, r_in.time);
         let attenuation = self.albedo;
-        Some((scattered, attenuation))
+        Some(ScatterRecord::new(scattered, attenuation))
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = rec.normal.dot(&scattered.dir.unit());
+        match cosine < 0.0 {
+            true => 0.0,
+            false => cosine / PI,
+        }
+    }
+
+    fn eval_bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.scattering_pdf(r_in, rec, scattered) * self.albedo
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- OREN-NAYAR -----
+// ----------------------------------------------------------------------
+// Rough diffuse reflection for clay, concrete and fabric. `roughness` is the
+// standard deviation of the microfacet slope angle in degrees; 0 is Lambertian.
+pub struct OrenNayar {
+    pub(crate) albedo: Color,
+    a: f64,
+    b: f64,
+}
+
+impl OrenNayar {
+    pub fn new(albedo: Color, roughness: f64) -> OrenNayar {
+        let sigma = degrees_to_radians(roughness);
+        let sigma2 = sigma * sigma;
+        OrenNayar {
+            albedo,
+            a: 1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+
+    // BSDF times cos theta_i for local directions
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let cos_i = cos_theta(wi);
+        let cos_o = cos_theta(wo);
+        if cos_i <= 0.0 || cos_o <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let sin_i = (1.0 - cos_i * cos_i).max(0.0).sqrt();
+        let sin_o = (1.0 - cos_o * cos_o).max(0.0).sqrt();
+
+        // cos(phi_i - phi_o), clamped to the forward half
+        let max_cos = match sin_i > 1e-4 && sin_o > 1e-4 {
+            true => ((wi.x() * wo.x() + wi.y() * wo.y()) / (sin_i * sin_o)).max(0.0),
+            false => 0.0,
+        };
+
+        let (sin_alpha, tan_beta) = match cos_i > cos_o {
+            true => (sin_o, sin_i / cos_i),
+            false => (sin_i, sin_o / cos_o),
+        };
+
+        (cos_i * (self.a + self.b * max_cos * sin_alpha * tan_beta) / PI) * self.albedo
+    }
+}
+
+impl Material for OrenNayar {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let wi = Vec3::random_cosine_direction();
+        let scattered = Ray::new_at(rec.p, frame.local_to_world(&wi), r_in.time);
+
+        let pdf = self.scattering_pdf(r_in, rec, &scattered);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let attenuation = self.eval(&wo, &wi) / pdf;
+        Some(ScatterRecord::new(scattered, attenuation))
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = rec.normal.dot(&scattered.dir.unit());
+        match cosine < 0.0 {
+            true => 0.0,
+            false => cosine / PI,
+        }
+    }
+
+    fn eval_bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let wi = frame.world_to_local(&scattered.dir.unit());
+        self.eval(&wo, &wi)
     }
 }
 
@@ -57,11 +348,39 @@ impl Material for Lambertian {
 #[derive(Clone, Copy)]
 pub struct Dielectric {
     pub(crate) reflection_index: f64,
+    // Beer-Lambert absorption coefficient per unit distance travelled inside the medium
+    pub(crate) absorption: Color,
+    pub(crate) thin_film: Option<ThinFilm>,
 }
 
 impl Dielectric {
     pub fn new(reflection_index: f64) -> Dielectric {
-        Dielectric { reflection_index }
+        Dielectric {
+            reflection_index,
+            absorption: Color::new(0.0, 0.0, 0.0),
+            thin_film: None,
+        }
+    }
+
+    pub fn with_absorption(reflection_index: f64, absorption: Color) -> Dielectric {
+        Dielectric {
+            reflection_index,
+            absorption,
+            thin_film: None,
+        }
+    }
+
+    pub fn with_thin_film(mut self, thin_film: ThinFilm) -> Dielectric {
+        self.thin_film = Some(thin_film);
+        self
+    }
+
+    fn transmittance(&self, distance: f64) -> Color {
+        Color::new(
+            (-self.absorption.r() * distance).exp(),
+            (-self.absorption.g() * distance).exp(),
+            (-self.absorption.b() * distance).exp(),
+        )
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -73,8 +392,12 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Vec3)> {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        // A back face hit means the ray travelled through the medium to get here
+        let attenuation = match rec.front_face {
+            true => Color::new(1.0, 1.0, 1.0),
+            false => self.transmittance(rec.t * r_in.dir.length()),
+        };
         let reflection_ratio = match rec.front_face {
             true => 1.0 / self.reflection_index,
             false => self.reflection_index,
@@ -90,15 +413,1431 @@ impl Material for Dielectric {
 
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
-        let direction = match reflection_ratio * sin_theta > 1.0
-            || Dielectric::reflectance(cos_theta, reflection_ratio) > random_double()
-        {
+        if reflection_ratio * sin_theta > 1.0 {
+            let scattered = Ray::new_at(
+                rec.p,
+                Vec3::reflect(&unit_direction, &rec.normal),
+                r_in.time,
+            );
+            return Some(ScatterRecord::specular(scattered, attenuation));
+        }
+
+        let reflectance = match self.thin_film {
+            None => {
+                let r = Dielectric::reflectance(cos_theta, reflection_ratio);
+                Color::new(r, r, r)
+            }
+            Some(thin_film) => {
+                let (outside, substrate) = match rec.front_face {
+                    true => (1.0, self.reflection_index),
+                    false => (self.reflection_index, 1.0),
+                };
+                thin_film.reflectance(cos_theta, outside, &[Complex::real(substrate); 3])
+            }
+        };
+
+        // Choose by the average reflectance and reweight the channels, which is a no-op
+        // for the colorless reflectance without a film.
+        let reflect_probability = (reflectance.r() + reflectance.g() + reflectance.b()) / 3.0;
+        let (direction, weight) = match reflect_probability > random_double() {
+            true => (
+                Vec3::reflect(&unit_direction, &rec.normal),
+                reflectance / reflect_probability,
+            ),
+            false => (
+                Vec3::refract(&unit_direction, &rec.normal, reflection_ratio),
+                (Color::new(1.0, 1.0, 1.0) - reflectance) / (1.0 - reflect_probability),
+            ),
+        };
+
+        let scattered = Ray::new_at(rec.p, direction, r_in.time);
+
+        Some(ScatterRecord::specular(scattered, attenuation * weight))
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- ROUGH DIELECTRIC -----
+// ----------------------------------------------------------------------
+// Microfacet glass (Walter et al. 2007) with GGX reflection and transmission,
+// for frosted glass, sandblasted plastics and rough ice.
+pub struct RoughDielectric {
+    pub(crate) reflection_index: f64,
+    pub(crate) distribution: TrowbridgeReitz,
+}
+
+impl RoughDielectric {
+    pub fn new(reflection_index: f64, roughness: f64) -> RoughDielectric {
+        let alpha = roughness_to_alpha(roughness);
+        RoughDielectric {
+            reflection_index,
+            distribution: TrowbridgeReitz::new(alpha, alpha),
+        }
+    }
+
+    // Relative IOR of the side we are refracting into
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        match rec.front_face {
+            true => self.reflection_index,
+            false => 1.0 / self.reflection_index,
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let eta = self.eta(rec);
+
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        if cos_theta(&wo) <= 0.0 {
+            return None;
+        }
+
+        let wm = self.distribution.sample_wm(&wo);
+        let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+
+        // Pick reflection or transmission proportional to the Fresnel term, so it cancels
+        // out of the weight together with D and one G1.
+        let wi = match random_double() < reflectance {
+            true => {
+                let wi = reflect(&wo, &wm);
+                if cos_theta(&wi) <= 0.0 {
+                    return None;
+                }
+                wi
+            }
+            false => match refract(&wo, &wm, eta) {
+                Some(wi) if cos_theta(&wi) < 0.0 => wi,
+                _ => return None,
+            },
+        };
+
+        let weight = self.distribution.g(&wo, &wi) / self.distribution.g1(&wo);
+        let attenuation = Color::new(weight, weight, weight);
+        let scattered = Ray::new_at(rec.p, frame.local_to_world(&wi), r_in.time);
+        Some(ScatterRecord::new(scattered, attenuation))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let eta = self.eta(rec);
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let wi = frame.world_to_local(&scattered.dir.unit());
+        if cos_theta(&wo) <= 0.0 || cos_theta(&wi) == 0.0 {
+            return 0.0;
+        }
+
+        match cos_theta(&wi) > 0.0 {
+            true => {
+                let wh = (wo + wi).unit();
+                fresnel_dielectric(wo.dot(&wh), eta) * self.distribution.visible_d(&wo, &wh)
+                    / (4.0 * wo.dot(&wh).abs())
+            }
+            false => match transmission_half_vector(&wo, &wi, eta) {
+                Some((wm, denom)) => {
+                    (1.0 - fresnel_dielectric(wo.dot(&wm), eta))
+                        * self.distribution.visible_d(&wo, &wm)
+                        * wi.dot(&wm).abs()
+                        / (denom * denom)
+                }
+                None => 0.0,
+            },
+        }
+    }
+
+    fn eval_bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let eta = self.eta(rec);
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let wi = frame.world_to_local(&scattered.dir.unit());
+        let cos_o = cos_theta(&wo);
+        if cos_o <= 0.0 || cos_theta(&wi) == 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let value = match cos_theta(&wi) > 0.0 {
+            true => {
+                let wh = (wo + wi).unit();
+                fresnel_dielectric(wo.dot(&wh), eta)
+                    * self.distribution.d(&wh)
+                    * self.distribution.g(&wo, &wi)
+                    / (4.0 * cos_o)
+            }
+            false => match transmission_half_vector(&wo, &wi, eta) {
+                Some((wm, denom)) => {
+                    (1.0 - fresnel_dielectric(wo.dot(&wm), eta))
+                        * self.distribution.d(&wm)
+                        * self.distribution.g(&wo, &wi)
+                        * (wi.dot(&wm) * wo.dot(&wm)).abs()
+                        / (cos_o * denom * denom)
+                }
+                None => 0.0,
+            },
+        };
+        Color::new(value, value, value)
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- PRINCIPLED -----
+// ----------------------------------------------------------------------
+// Disney style uber material, close to the glTF metallic-roughness model.
+// Every parameter is a texture; scalar parameters are read from the first channel.
+pub struct Principled {
+    pub(crate) base_color: Arc<dyn Texture>,
+    pub(crate) metallic: Arc<dyn Texture>,
+    pub(crate) roughness: Arc<dyn Texture>,
+    pub(crate) specular: Arc<dyn Texture>,
+    pub(crate) clearcoat: Arc<dyn Texture>,
+    pub(crate) clearcoat_roughness: Arc<dyn Texture>,
+    pub(crate) sheen: Arc<dyn Texture>,
+    pub(crate) transmission: Arc<dyn Texture>,
+    pub(crate) emission: Arc<dyn Texture>,
+    pub(crate) reflection_index: f64,
+}
+
+// Principled parameters evaluated at a single hit point
+struct PrincipledLobes {
+    base_color: Color,
+    metallic: f64,
+    roughness: f64,
+    transmission: f64,
+    sheen: Color,
+    clearcoat: f64,
+    // Relative IOR of the side a transmitted ray refracts into
+    eta: f64,
+    specular_f0: Color,
+    specular: TrowbridgeReitz,
+    clearcoat_distribution: TrowbridgeReitz,
+    // Lobe selection probabilities: diffuse, specular, clearcoat, transmission
+    probabilities: [f64; 4],
+}
+
+impl Principled {
+    pub fn new(base_color: Arc<dyn Texture>) -> Principled {
+        Principled {
+            base_color,
+            metallic: Arc::new(SolidColor::scalar(0.0)),
+            roughness: Arc::new(SolidColor::scalar(0.5)),
+            specular: Arc::new(SolidColor::scalar(0.5)),
+            clearcoat: Arc::new(SolidColor::scalar(0.0)),
+            clearcoat_roughness: Arc::new(SolidColor::scalar(0.03)),
+            sheen: Arc::new(SolidColor::scalar(0.0)),
+            transmission: Arc::new(SolidColor::scalar(0.0)),
+            emission: Arc::new(SolidColor::scalar(0.0)),
+            reflection_index: 1.5,
+        }
+    }
+
+    pub fn with_metallic(mut self, metallic: Arc<dyn Texture>) -> Principled {
+        self.metallic = metallic;
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: Arc<dyn Texture>) -> Principled {
+        self.roughness = roughness;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_specular(mut self, specular: Arc<dyn Texture>) -> Principled {
+        self.specular = specular;
+        self
+    }
+
+    pub fn with_clearcoat(
+        mut self,
+        clearcoat: Arc<dyn Texture>,
+        clearcoat_roughness: Arc<dyn Texture>,
+    ) -> Principled {
+        self.clearcoat = clearcoat;
+        self.clearcoat_roughness = clearcoat_roughness;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_sheen(mut self, sheen: Arc<dyn Texture>) -> Principled {
+        self.sheen = sheen;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_transmission(
+        mut self,
+        transmission: Arc<dyn Texture>,
+        reflection_index: f64,
+    ) -> Principled {
+        self.transmission = transmission;
+        self.reflection_index = reflection_index;
+        self
+    }
+
+    pub fn with_emission(mut self, emission: Arc<dyn Texture>) -> Principled {
+        self.emission = emission;
+        self
+    }
+
+    fn lobes(&self, rec: &HitRecord, cos_theta_o: f64) -> PrincipledLobes {
+        let (u, v, p) = (rec.u, rec.v, &rec.p);
+        let base_color = self.base_color.value(u, v, p);
+        let metallic = clamp(self.metallic.scalar(u, v, p), 0.0, 1.0);
+        let roughness = clamp(self.roughness.scalar(u, v, p), 0.0, 1.0);
+        let specular = self.specular.scalar(u, v, p).max(0.0);
+        let clearcoat = self.clearcoat.scalar(u, v, p).max(0.0);
+        let clearcoat_roughness = clamp(self.clearcoat_roughness.scalar(u, v, p), 0.0, 1.0);
+        let transmission = clamp(self.transmission.scalar(u, v, p), 0.0, 1.0);
+        let sheen = self.sheen.value(u, v, p);
+
+        // Dielectric F0 of 0.08 * specular, so the default of 0.5 matches an IOR of 1.5
+        let dielectric_f0 = 0.08 * Color::new(specular, specular, specular);
+        let specular_f0 = (1.0 - metallic) * dielectric_f0 + metallic * base_color;
+
+        let alpha = roughness_to_alpha(roughness);
+        let clearcoat_alpha = roughness_to_alpha(clearcoat_roughness);
+        let eta = match rec.front_face {
+            true => self.reflection_index,
+            false => 1.0 / self.reflection_index,
+        };
+
+        let mut probabilities = [
+            (1.0 - metallic) * (1.0 - transmission) * base_color.luminance(),
+            fresnel_schlick(&specular_f0, cos_theta_o).luminance(),
+            0.25 * clearcoat * fresnel_schlick(&Color::new(0.04, 0.04, 0.04), cos_theta_o).r(),
+            (1.0 - metallic) * transmission * base_color.luminance(),
+        ];
+        let total: f64 = probabilities.iter().sum();
+        if total > 0.0 {
+            for probability in probabilities.iter_mut() {
+                *probability /= total;
+            }
+        }
+
+        PrincipledLobes {
+            base_color,
+            metallic,
+            roughness,
+            transmission,
+            sheen,
+            clearcoat,
+            eta,
+            specular_f0,
+            specular: TrowbridgeReitz::new(alpha, alpha),
+            clearcoat_distribution: TrowbridgeReitz::new(clearcoat_alpha, clearcoat_alpha),
+            probabilities,
+        }
+    }
+}
+
+impl PrincipledLobes {
+    // BSDF times |cos theta_i| for a pair of local directions
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let cos_o = cos_theta(wo);
+        let cos_i = cos_theta(wi);
+        let mut f = Color::new(0.0, 0.0, 0.0);
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return f;
+        }
+
+        if cos_i > 0.0 {
+            let wh = (*wo + *wi).unit();
+            let cos_d = wi.dot(&wh);
+
+            // Disney diffuse with retro-reflection, plus the sheen rim
+            let diffuse_weight = (1.0 - self.metallic) * (1.0 - self.transmission);
+            if diffuse_weight > 0.0 {
+                let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+                let fl = 1.0 + (fd90 - 1.0) * schlick_weight(cos_i);
+                let fv = 1.0 + (fd90 - 1.0) * schlick_weight(cos_o);
+                f = f + (diffuse_weight * fl * fv * cos_i / PI) * self.base_color;
+                f = f + (diffuse_weight * schlick_weight(cos_d) * cos_i) * self.sheen;
+            }
+
+            // GGX specular
+            let fresnel = fresnel_schlick(&self.specular_f0, cos_d);
+            let specular = self.specular.d(&wh) * self.specular.g(wo, wi) / (4.0 * cos_o);
+            f = f + specular * fresnel;
+
+            // Clearcoat
+            if self.clearcoat > 0.0 {
+                let fresnel = fresnel_schlick(&Color::new(0.04, 0.04, 0.04), cos_d).r();
+                let coat = self.clearcoat_distribution.d(&wh)
+                    * self.clearcoat_distribution.g(wo, wi)
+                    / (4.0 * cos_o);
+                f = f + (0.25 * self.clearcoat * fresnel * coat) * Color::new(1.0, 1.0, 1.0);
+            }
+        } else {
+            // Rough transmission through the generalized half vector
+            let transmission_weight = (1.0 - self.metallic) * self.transmission;
+            if let Some((wm, denom)) = transmission_half_vector(wo, wi, self.eta) {
+                let fresnel = fresnel_dielectric(wo.dot(&wm), self.eta);
+                let t = self.specular.d(&wm)
+                    * self.specular.g(wo, wi)
+                    * (wi.dot(&wm) * wo.dot(&wm)).abs()
+                    / (cos_o * denom * denom);
+                f = f + (transmission_weight * (1.0 - fresnel) * t) * self.base_color;
+            }
+        }
+        f
+    }
+
+    // Probability density of sampling `wi` with the lobe mixture
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let cos_i = cos_theta(wi);
+        let [p_diffuse, p_specular, p_clearcoat, p_transmission] = self.probabilities;
+        if cos_i > 0.0 {
+            let wh = (*wo + *wi).unit();
+            let jacobian = 1.0 / (4.0 * wo.dot(&wh).abs());
+            p_diffuse * cos_i / PI
+                + p_specular * self.specular.visible_d(wo, &wh) * jacobian
+                + p_clearcoat * self.clearcoat_distribution.visible_d(wo, &wh) * jacobian
+        } else {
+            match transmission_half_vector(wo, wi, self.eta) {
+                Some((wm, denom)) => {
+                    p_transmission * self.specular.visible_d(wo, &wm) * wi.dot(&wm).abs()
+                        / (denom * denom)
+                }
+                None => 0.0,
+            }
+        }
+    }
+
+    fn sample(&self, wo: &Vec3) -> Option<Vec3> {
+        let [p_diffuse, p_specular, p_clearcoat, _] = self.probabilities;
+        let xi = random_double();
+        if xi < p_diffuse {
+            Some(Vec3::random_cosine_direction())
+        } else if xi < p_diffuse + p_specular {
+            Some(reflect(wo, &self.specular.sample_wm(wo)))
+        } else if xi < p_diffuse + p_specular + p_clearcoat {
+            Some(reflect(wo, &self.clearcoat_distribution.sample_wm(wo)))
+        } else {
+            refract(wo, &self.specular.sample_wm(wo), self.eta)
+        }
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        if cos_theta(&wo) <= 0.0 {
+            return None;
+        }
+
+        let lobes = self.lobes(rec, cos_theta(&wo));
+        let wi = lobes.sample(&wo)?;
+
+        // One-sample MIS over the lobes: weight by the full mixture instead of the picked lobe
+        let pdf = lobes.pdf(&wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let attenuation = lobes.eval(&wo, &wi) / pdf;
+        let scattered = Ray::new_at(rec.p, frame.local_to_world(&wi), r_in.time);
+        Some(ScatterRecord::new(scattered, attenuation))
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.emission.value(rec.u, rec.v, &rec.p)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let wi = frame.world_to_local(&scattered.dir.unit());
+        if cos_theta(&wo) <= 0.0 {
+            return 0.0;
+        }
+        self.lobes(rec, cos_theta(&wo)).pdf(&wo, &wi)
+    }
+
+    fn eval_bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let wi = frame.world_to_local(&scattered.dir.unit());
+        self.lobes(rec, cos_theta(&wo)).eval(&wo, &wi)
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- COATED -----
+// ----------------------------------------------------------------------
+// Thin dielectric coating over an arbitrary base material, e.g. varnish over wood
+// or a clearcoat over car paint. The coat reflects with probability given by its
+// Fresnel term, everything else is handed to the base material.
+pub struct Coated {
+    pub(crate) base: Arc<dyn Material>,
+    pub(crate) reflection_index: f64,
+    // None for a perfectly smooth coat
+    pub(crate) distribution: Option<TrowbridgeReitz>,
+}
+
+impl Coated {
+    pub fn new(base: Arc<dyn Material>, reflection_index: f64, roughness: f64) -> Coated {
+        let distribution = match roughness > 0.0 {
+            true => {
+                let alpha = roughness_to_alpha(roughness);
+                Some(TrowbridgeReitz::new(alpha, alpha))
+            }
+            false => None,
+        };
+        Coated {
+            base,
+            reflection_index,
+            distribution,
+        }
+    }
+
+    // Probability of scattering off the coat rather than the base
+    fn coat_probability(&self, rec: &HitRecord, cos_theta_o: f64) -> f64 {
+        match rec.front_face {
+            true => fresnel_dielectric(cos_theta_o, self.reflection_index),
+            false => 0.0,
+        }
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let coat_probability = self.coat_probability(rec, cos_theta(&wo));
+        if random_double() >= coat_probability {
+            return self.base.scatter(r_in, rec);
+        }
+
+        let (wi, weight) = match self.distribution {
+            None => (reflect(&wo, &Vec3::new(0.0, 0.0, 1.0)), 1.0),
+            Some(distribution) => {
+                let wm = distribution.sample_wm(&wo);
+                let wi = reflect(&wo, &wm);
+                if cos_theta(&wi) <= 0.0 {
+                    return None;
+                }
+                // The coat was picked with the macro surface Fresnel term, correct for the
+                // microfacet one.
+                let fresnel = fresnel_dielectric(wo.dot(&wm), self.reflection_index);
+                let weight =
+                    fresnel / coat_probability * distribution.g(&wo, &wi) / distribution.g1(&wo);
+                (wi, weight)
+            }
+        };
+
+        let scattered = Ray::new_at(rec.p, frame.local_to_world(&wi), r_in.time);
+        Some(ScatterRecord {
+            scattered,
+            attenuation: Color::new(weight, weight, weight),
+            is_specular: self.distribution.is_none(),
+        })
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.base.emitted(rec)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let wi = frame.world_to_local(&scattered.dir.unit());
+        let coat_probability = self.coat_probability(rec, cos_theta(&wo));
+
+        let coat_pdf = match self.distribution {
+            Some(distribution) if cos_theta(&wi) > 0.0 => {
+                let wh = (wo + wi).unit();
+                distribution.visible_d(&wo, &wh) / (4.0 * wo.dot(&wh).abs())
+            }
+            _ => 0.0,
+        };
+        coat_probability * coat_pdf
+            + (1.0 - coat_probability) * self.base.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn eval_bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let wi = frame.world_to_local(&scattered.dir.unit());
+        let coat_probability = self.coat_probability(rec, cos_theta(&wo));
+
+        let coat = match self.distribution {
+            Some(distribution) if rec.front_face && cos_theta(&wi) > 0.0 => {
+                let wh = (wo + wi).unit();
+                fresnel_dielectric(wo.dot(&wh), self.reflection_index)
+                    * distribution.d(&wh)
+                    * distribution.g(&wo, &wi)
+                    / (4.0 * cos_theta(&wo))
+            }
+            _ => 0.0,
+        };
+        Color::new(coat, coat, coat)
+            + (1.0 - coat_probability) * self.base.eval_bsdf(r_in, rec, scattered)
+    }
+
+    fn interior_medium(&self, rec: &HitRecord) -> Option<&Medium> {
+        self.base.interior_medium(rec)
+    }
+
+    fn is_cutout(&self, rec: &HitRecord) -> bool {
+        self.base.is_cutout(rec)
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- SUBSURFACE -----
+// ----------------------------------------------------------------------
+// Random walk subsurface scattering for skin, wax, marble and milk. The boundary
+// is a smooth dielectric; inside, rays scatter through a homogeneous medium until
+// they find their way out of the object again.
+pub struct Subsurface {
+    pub(crate) reflection_index: f64,
+    pub(crate) medium: Medium,
+}
+
+impl Subsurface {
+    // `albedo` is the resulting multiple scattering color and `mean_free_path` the
+    // average distance per channel light travels before scattering inside.
+    pub fn new(albedo: Color, mean_free_path: Color, reflection_index: f64, g: f64) -> Subsurface {
+        let channel = |albedo: f64, mean_free_path: f64| {
+            // Invert the multiple scattering albedo to a single scattering one (Chiang et al. 2016)
+            let a = clamp(albedo, 0.0, 0.999);
+            let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+            let single_scattering_albedo = 1.0 - s * s;
+            let sigma_t = 1.0 / mean_free_path.max(1e-6);
+            (
+                sigma_t * (1.0 - single_scattering_albedo),
+                sigma_t * single_scattering_albedo,
+            )
+        };
+        let (r_a, r_s) = channel(albedo.r(), mean_free_path.r());
+        let (g_a, g_s) = channel(albedo.g(), mean_free_path.g());
+        let (b_a, b_s) = channel(albedo.b(), mean_free_path.b());
+
+        Subsurface {
+            reflection_index,
+            medium: Medium::new(Color::new(r_a, g_a, b_a), Color::new(r_s, g_s, b_s), g),
+        }
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let eta = match rec.front_face {
+            true => self.reflection_index,
+            false => 1.0 / self.reflection_index,
+        };
+        let unit_direction = r_in.dir.unit();
+        let cos_theta = clamp(-unit_direction.dot(&rec.normal), 0.0, 1.0);
+
+        let direction = match fresnel_dielectric(cos_theta, eta) > random_double() {
             true => Vec3::reflect(&unit_direction, &rec.normal),
-            false => Vec3::refract(&unit_direction, &rec.normal, reflection_ratio),
+            false => Vec3::refract(&unit_direction, &rec.normal, 1.0 / eta),
         };
+        Some(ScatterRecord::specular(
+            Ray::new_at(rec.p, direction, r_in.time),
+            Color::new(1.0, 1.0, 1.0),
+        ))
+    }
+
+    fn interior_medium(&self, _rec: &HitRecord) -> Option<&Medium> {
+        Some(&self.medium)
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- MIX -----
+// ----------------------------------------------------------------------
+// Stochastically blends two materials, e.g. rust over metal or decals over paint.
+// The mask is read as a scalar weight: 0 picks `first`, 1 picks `second`.
+// Each hit picks one of the two by hashing its position, so scattering, light
+// sampling, cutouts and interior media at a hit all follow the same material.
+pub struct MixMaterial {
+    pub(crate) first: Arc<dyn Material>,
+    pub(crate) second: Arc<dyn Material>,
+    pub(crate) mask: Arc<dyn Texture>,
+}
 
-        let scattered = Ray::new(rec.p, direction);
+impl MixMaterial {
+    #[allow(dead_code)]
+    pub fn new(first: Arc<dyn Material>, second: Arc<dyn Material>, weight: f64) -> MixMaterial {
+        MixMaterial::with_mask(first, second, Arc::new(SolidColor::scalar(weight)))
+    }
 
-        Some((scattered, attenuation))
+    pub fn with_mask(
+        first: Arc<dyn Material>,
+        second: Arc<dyn Material>,
+        mask: Arc<dyn Texture>,
+    ) -> MixMaterial {
+        MixMaterial {
+            first,
+            second,
+            mask,
+        }
+    }
+
+    fn weight(&self, rec: &HitRecord) -> f64 {
+        clamp(self.mask.scalar(rec.u, rec.v, &rec.p), 0.0, 1.0)
+    }
+
+    // Material picked at the hit, `second` with probability `weight` over all hits
+    fn pick(&self, rec: &HitRecord) -> &dyn Material {
+        let xi = hash_double(&[rec.p.x(), rec.p.y(), rec.p.z(), rec.u, rec.v]);
+        match xi < self.weight(rec) {
+            true => self.second.as_ref(),
+            false => self.first.as_ref(),
+        }
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.pick(rec).scatter(r_in, rec)
+    }
+
+    // Emission does not depend on the scattered direction, so it is blended exactly
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        let weight = self.weight(rec);
+        (1.0 - weight) * self.first.emitted(rec) + weight * self.second.emitted(rec)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.pick(rec).scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn eval_bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.pick(rec).eval_bsdf(r_in, rec, scattered)
+    }
+
+    // Rays only enter the medium of the material picked at the hit, since only that
+    // one refracted them
+    fn interior_medium(&self, rec: &HitRecord) -> Option<&Medium> {
+        self.pick(rec).interior_medium(rec)
+    }
+
+    fn is_cutout(&self, rec: &HitRecord) -> bool {
+        self.pick(rec).is_cutout(rec)
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- NORMAL MAPPED -----
+// ----------------------------------------------------------------------
+// Perturbs the shading normal of another material with a tangent space normal map,
+// with x along dp/du, y along dp/dv and z along the surface normal.
+pub struct NormalMapped {
+    pub(crate) inner: Arc<dyn Material>,
+    pub(crate) normal_map: Arc<dyn Texture>,
+    pub(crate) strength: f64,
+}
+
+impl NormalMapped {
+    #[allow(dead_code)]
+    pub fn new(
+        inner: Arc<dyn Material>,
+        normal_map: Arc<dyn Texture>,
+        strength: f64,
+    ) -> NormalMapped {
+        NormalMapped {
+            inner,
+            normal_map,
+            strength,
+        }
+    }
+
+    fn perturb(&self, rec: &HitRecord) -> HitRecord {
+        let encoded = self.normal_map.value(rec.u, rec.v, &rec.p);
+        let tangent_normal = Vec3::new(
+            self.strength * (2.0 * encoded.x() - 1.0),
+            self.strength * (2.0 * encoded.y() - 1.0),
+            2.0 * encoded.z() - 1.0,
+        );
+
+        let frame = rec.shading_frame();
+        // Keep the bitangent pointing along dp/dv, whatever side the normal faces
+        let bitangent = match frame.v.dot(&rec.dpdv) < 0.0 {
+            true => -frame.v,
+            false => frame.v,
+        };
+        let normal = (tangent_normal.x() * frame.u
+            + tangent_normal.y() * bitangent
+            + tangent_normal.z() * frame.w)
+            .unit();
+        with_shading_normal(rec, normal)
+    }
+}
+
+impl Material for NormalMapped {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.inner.scatter(r_in, &self.perturb(rec))
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.inner.emitted(rec)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.inner
+            .scattering_pdf(r_in, &self.perturb(rec), scattered)
+    }
+
+    fn interior_medium(&self, rec: &HitRecord) -> Option<&Medium> {
+        self.inner.interior_medium(rec)
+    }
+
+    fn eval_bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.inner.eval_bsdf(r_in, &self.perturb(rec), scattered)
+    }
+
+    fn is_cutout(&self, rec: &HitRecord) -> bool {
+        self.inner.is_cutout(rec)
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- BUMP MAPPED -----
+// ----------------------------------------------------------------------
+// Perturbs the shading normal of another material as if the surface was displaced
+// along its normal by `scale` times the height texture.
+pub struct BumpMapped {
+    pub(crate) inner: Arc<dyn Material>,
+    pub(crate) height: Arc<dyn Texture>,
+    pub(crate) scale: f64,
+}
+
+impl BumpMapped {
+    #[allow(dead_code)]
+    pub fn new(inner: Arc<dyn Material>, height: Arc<dyn Texture>, scale: f64) -> BumpMapped {
+        BumpMapped {
+            inner,
+            height,
+            scale,
+        }
+    }
+
+    fn perturb(&self, rec: &HitRecord) -> HitRecord {
+        // Finite differences of the displacement over a small step in u and v
+        let delta = 0.0005;
+        let displacement = |u: f64, v: f64, p: &Point3| self.scale * self.height.scalar(u, v, p);
+        let d = displacement(rec.u, rec.v, &rec.p);
+        let d_u = displacement(rec.u + delta, rec.v, &(rec.p + delta * rec.dpdu));
+        let d_v = displacement(rec.u, rec.v + delta, &(rec.p + delta * rec.dpdv));
+
+        let dpdu = rec.dpdu + ((d_u - d) / delta) * rec.normal;
+        let dpdv = rec.dpdv + ((d_v - d) / delta) * rec.normal;
+        let mut normal = dpdu.cross(dpdv).unit();
+        if normal.dot(&rec.normal) < 0.0 {
+            normal = -normal;
+        }
+        with_shading_normal(rec, normal)
+    }
+}
+
+impl Material for BumpMapped {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.inner.scatter(r_in, &self.perturb(rec))
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.inner.emitted(rec)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.inner
+            .scattering_pdf(r_in, &self.perturb(rec), scattered)
+    }
+
+    fn interior_medium(&self, rec: &HitRecord) -> Option<&Medium> {
+        self.inner.interior_medium(rec)
+    }
+
+    fn eval_bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.inner.eval_bsdf(r_in, &self.perturb(rec), scattered)
+    }
+
+    fn is_cutout(&self, rec: &HitRecord) -> bool {
+        self.inner.is_cutout(rec)
+    }
+}
+
+// Copy of `rec` shading with `normal`. Degenerate or back facing perturbations fall
+// back to the geometric normal.
+fn with_shading_normal(rec: &HitRecord, normal: Vec3) -> HitRecord {
+    let mut perturbed = rec.clone();
+    if normal.dot(&rec.normal) > 0.0 && !normal.e.iter().any(|x| x.is_nan()) {
+        perturbed.normal = normal;
+    }
+    perturbed
+}
+
+// ----------------------------------------------------------------------
+// ----- ALPHA MASK -----
+// ----------------------------------------------------------------------
+// Cuts holes into surfaces wherever the opacity texture falls below the threshold,
+// for leaves, fences and chain-link. Elsewhere it behaves like the inner material.
+pub struct AlphaMask {
+    pub(crate) inner: Arc<dyn Material>,
+    pub(crate) opacity: Arc<dyn Texture>,
+    pub(crate) threshold: f64,
+}
+
+impl AlphaMask {
+    pub fn new(inner: Arc<dyn Material>, opacity: Arc<dyn Texture>, threshold: f64) -> AlphaMask {
+        AlphaMask {
+            inner,
+            opacity,
+            threshold,
+        }
+    }
+}
+
+impl Material for AlphaMask {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.inner.scatter(r_in, rec)
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.inner.emitted(rec)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.inner.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn interior_medium(&self, rec: &HitRecord) -> Option<&Medium> {
+        self.inner.interior_medium(rec)
+    }
+
+    fn is_cutout(&self, rec: &HitRecord) -> bool {
+        self.opacity.scalar(rec.u, rec.v, &rec.p) < self.threshold || self.inner.is_cutout(rec)
+    }
+
+    fn eval_bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.inner.eval_bsdf(r_in, rec, scattered)
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- SHEEN -----
+// ----------------------------------------------------------------------
+// Velvet and cloth: a sheen lobe with the Charlie microfiber distribution
+// (Estevez & Kulla 2017) and Neubelt's visibility term, layered on top of a base
+// material such as `Lambertian` or `OrenNayar` for the characteristic rim brightening.
+pub struct Sheen {
+    pub(crate) base: Arc<dyn Material>,
+    pub(crate) color: Color,
+    pub(crate) alpha: f64,
+}
+
+impl Sheen {
+    pub fn new(base: Arc<dyn Material>, color: Color, roughness: f64) -> Sheen {
+        Sheen {
+            base,
+            color,
+            alpha: clamp(roughness * roughness, 0.07 * 0.07, 1.0),
+        }
+    }
+
+    // Probability of sampling the sheen lobe instead of the base material
+    fn sheen_probability(&self) -> f64 {
+        clamp(self.color.luminance(), 0.0, 0.5)
+    }
+
+    // Sheen BSDF times cos theta_i for local directions
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let cos_o = cos_theta(wo);
+        let cos_i = cos_theta(wi);
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let d = self.distribution(&(*wo + *wi).unit());
+        let visibility = 1.0 / (4.0 * (cos_i + cos_o - cos_i * cos_o));
+        (d * visibility * cos_i) * self.color
+    }
+
+    // Charlie distribution of microfiber normals
+    fn distribution(&self, wh: &Vec3) -> f64 {
+        let sin_h = (1.0 - cos_theta(wh).powi(2)).max(0.0).sqrt();
+        let inv_alpha = 1.0 / self.alpha;
+        (2.0 + inv_alpha) * sin_h.powf(inv_alpha) / (2.0 * PI)
+    }
+}
+
+impl Material for Sheen {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let sheen_probability = self.sheen_probability();
+        if random_double() >= sheen_probability {
+            let mut record = self.base.scatter(r_in, rec)?;
+            record.attenuation = record.attenuation / (1.0 - sheen_probability);
+            return Some(record);
+        }
+
+        // The sheen lobe is broad, cosine sampling matches it well
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let wi = Vec3::random_cosine_direction();
+        let pdf = sheen_probability * cos_theta(&wi) / PI;
+        if pdf <= 0.0 {
+            return None;
+        }
+        let scattered = Ray::new_at(rec.p, frame.local_to_world(&wi), r_in.time);
+        Some(ScatterRecord::new(scattered, self.eval(&wo, &wi) / pdf))
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.base.emitted(rec)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let sheen_probability = self.sheen_probability();
+        let cosine = rec.normal.dot(&scattered.dir.unit()).max(0.0);
+        sheen_probability * cosine / PI
+            + (1.0 - sheen_probability) * self.base.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn interior_medium(&self, rec: &HitRecord) -> Option<&Medium> {
+        self.base.interior_medium(rec)
+    }
+
+    fn is_cutout(&self, rec: &HitRecord) -> bool {
+        self.base.is_cutout(rec)
+    }
+
+    fn eval_bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let frame = rec.shading_frame();
+        let wo = frame.world_to_local(&-r_in.dir.unit());
+        let wi = frame.world_to_local(&scattered.dir.unit());
+        self.eval(&wo, &wi) + self.base.eval_bsdf(r_in, rec, scattered)
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- DIFFUSE LIGHT -----
+// ----------------------------------------------------------------------
+// Emits light evenly in all directions and absorbs everything arriving
+pub struct DiffuseLight {
+    pub(crate) emit: Arc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> DiffuseLight {
+        DiffuseLight {
+            emit: Arc::new(SolidColor::new(emit)),
+        }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.emit.value(rec.u, rec.v, &rec.p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hit at the origin of a surface in the xy plane, normal facing the incoming ray
+    fn hit(material: Arc<dyn Material>, front_face: bool, t: f64) -> HitRecord {
+        let mut rec = HitRecord::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            material,
+            t,
+            0.5,
+            0.5,
+            front_face,
+        );
+        rec.set_tangents(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        rec
+    }
+
+    // Ray leaving the hit point towards the direction with polar angles (theta, phi)
+    fn outgoing(theta: f64, phi: f64) -> Ray {
+        let dir = Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        );
+        Ray::new(Point3::new(0.0, 0.0, 0.0), dir)
+    }
+
+    // Midpoint rule over the upper hemisphere of the hit
+    fn integrate_hemisphere<F: Fn(&Ray) -> f64>(f: F) -> f64 {
+        let (n_theta, n_phi) = (400, 200);
+        let d_theta = PI / 2.0 / n_theta as f64;
+        let d_phi = 2.0 * PI / n_phi as f64;
+        let mut sum = 0.0;
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                sum += f(&outgoing(theta, phi)) * theta.sin() * d_theta * d_phi;
+            }
+        }
+        sum
+    }
+
+    // Texture reading back the surface coordinates as a color
+    struct UvTexture;
+
+    impl Texture for UvTexture {
+        fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+            Color::new(u, v, 0.0)
+        }
+    }
+
+    #[test]
+    fn dielectric_absorbs_along_the_path_inside() {
+        let absorption = Color::new(0.5, 1.0, 2.0);
+        let material = Arc::new(Dielectric::with_absorption(1.5, absorption));
+        let r_in = Ray::new(Point3::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -2.0));
+
+        // Travelled t * |dir| = 3 units inside the medium before the back face
+        let rec = hit(material.clone(), false, 1.5);
+        let attenuation = material.scatter(&r_in, &rec).unwrap().attenuation;
+        assert!((attenuation.r() - (-1.5f64).exp()).abs() < 1e-9);
+        assert!((attenuation.g() - (-3.0f64).exp()).abs() < 1e-9);
+        assert!((attenuation.b() - (-6.0f64).exp()).abs() < 1e-9);
+
+        // Entering the medium attenuates nothing yet
+        let rec = hit(material.clone(), true, 1.5);
+        let attenuation = material.scatter(&r_in, &rec).unwrap().attenuation;
+        assert!((attenuation.r() - 1.0).abs() < 1e-9);
+        assert!((attenuation.b() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn smooth_conductor_reflects_its_fresnel_reflectance() {
+        let material = Arc::new(Conductor::gold(0.0));
+        let r_in = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = hit(material.clone(), true, 1.0);
+        let scatter = material.scatter(&r_in, &rec).unwrap();
+
+        let expected = fresnel_complex_color(1.0, &material.eta, &material.k);
+        assert!((scatter.attenuation.r() - expected.r()).abs() < 1e-3);
+        assert!((scatter.attenuation.g() - expected.g()).abs() < 1e-3);
+        assert!((scatter.attenuation.b() - expected.b()).abs() < 1e-3);
+        assert!(scatter.scattered.dir.unit().z() > 0.999);
+    }
+
+    #[test]
+    fn rough_dielectric_conserves_energy() {
+        for &front_face in &[true, false] {
+            let material = Arc::new(RoughDielectric::new(1.5, 0.3));
+            let r_in = Ray::new(Point3::new(0.3, 0.0, 1.0), Vec3::new(-0.3, 0.0, -1.0));
+            let rec = hit(material.clone(), front_face, 1.0);
+
+            let samples = 20_000;
+            let mut total = 0.0;
+            for _ in 0..samples {
+                if let Some(scatter) = material.scatter(&r_in, &rec) {
+                    let weight = scatter.attenuation.r();
+                    assert!(weight <= 1.0 + 1e-9);
+                    total += weight;
+
+                    // The sampled weight is the BSDF over the pdf of its direction
+                    let f = material.eval_bsdf(&r_in, &rec, &scatter.scattered).r();
+                    let pdf = material.scattering_pdf(&r_in, &rec, &scatter.scattered);
+                    assert!((f / pdf - weight).abs() < 1e-6 * (1.0 + weight));
+                }
+            }
+            let albedo = total / samples as f64;
+            assert!(albedo <= 1.0 && albedo > 0.9, "{}", albedo);
+        }
+    }
+
+    #[test]
+    fn principled_pdf_integrates_to_one() {
+        let material = Arc::new(
+            Principled::new(Arc::new(SolidColor::new(Color::new(0.8, 0.5, 0.2))))
+                .with_roughness(Arc::new(SolidColor::scalar(0.4)))
+                .with_clearcoat(
+                    Arc::new(SolidColor::scalar(1.0)),
+                    Arc::new(SolidColor::scalar(0.1)),
+                ),
+        );
+        let r_in = Ray::new(Point3::new(0.5, 0.0, 1.0), Vec3::new(-0.5, 0.0, -1.0));
+        let rec = hit(material.clone(), true, 1.0);
+
+        // Only the specular lobes lose the few reflections that end up below the surface
+        let total =
+            integrate_hemisphere(|scattered| material.scattering_pdf(&r_in, &rec, scattered));
+        assert!(total > 0.97 && total < 1.001, "{}", total);
+    }
+
+    #[test]
+    fn principled_reads_textures_at_the_hit_uv() {
+        let material = Arc::new(
+            Principled::new(Arc::new(SolidColor::new(Color::new(1.0, 1.0, 1.0))))
+                .with_emission(Arc::new(UvTexture))
+                .with_metallic(Arc::new(UvTexture)),
+        );
+        let mut rec = hit(material.clone(), true, 1.0);
+        rec.u = 0.25;
+        rec.v = 0.75;
+        let emitted = material.emitted(&rec);
+        assert!((emitted.r() - 0.25).abs() < 1e-12 && (emitted.g() - 0.75).abs() < 1e-12);
+
+        // Fully metallic at u = 1 reflects the white base color without a diffuse lobe
+        rec.u = 1.0;
+        let lobes = material.lobes(&rec, 1.0);
+        assert_eq!(lobes.probabilities[0], 0.0);
+        assert!((lobes.probabilities[1] - 1.0).abs() < 1e-12);
+        assert!((lobes.specular_f0.r() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn oren_nayar_without_roughness_is_lambertian() {
+        let albedo = Color::new(0.8, 0.4, 0.2);
+        let material = Arc::new(OrenNayar::new(albedo, 0.0));
+        let r_in = Ray::new(Point3::new(0.6, 0.0, 1.0), Vec3::new(-0.6, 0.0, -1.0));
+        let rec = hit(material.clone(), true, 1.0);
+        for &(theta, phi) in &[(0.0, 0.0), (0.5, 0.3), (1.2, 3.0)] {
+            let scattered = outgoing(theta, phi);
+            let f = material.eval_bsdf(&r_in, &rec, &scattered);
+            let expected = albedo.r() * f64::cos(theta) / PI;
+            assert!((f.r() - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn rough_oren_nayar_does_not_create_energy() {
+        let material = Arc::new(OrenNayar::new(Color::new(1.0, 1.0, 1.0), 30.0));
+        let rec = hit(material.clone(), true, 1.0);
+        for &theta in &[0.0, 0.8, 1.4] {
+            let r_in = Ray::new(outgoing(theta, 0.0).dir, -outgoing(theta, 0.0).dir);
+            let albedo =
+                integrate_hemisphere(|scattered| material.eval_bsdf(&r_in, &rec, scattered).r());
+            assert!(albedo <= 1.0 && albedo > 0.7, "{} {}", theta, albedo);
+        }
+    }
+
+    #[test]
+    fn smooth_coat_reflects_its_fresnel_reflectance() {
+        let black = Arc::new(Lambertian::new(Color::new(0.0, 0.0, 0.0)));
+        let material = Arc::new(Coated::new(black, 1.5, 0.0));
+        let r_in = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = hit(material.clone(), true, 1.0);
+
+        // Only the coat reflects anything off a black base: 4% at normal incidence
+        let samples = 100_000;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            let scatter = material.scatter(&r_in, &rec).unwrap();
+            if scatter.is_specular {
+                assert!(scatter.scattered.dir.unit().z() > 0.999);
+            }
+            total += scatter.attenuation.r();
+        }
+        let reflectance = total / samples as f64;
+        assert!((reflectance - 0.04).abs() < 0.004, "{}", reflectance);
+    }
+
+    #[test]
+    fn coat_leaves_the_transmitted_share_to_the_base() {
+        let white = Arc::new(Lambertian::new(Color::new(1.0, 1.0, 1.0)));
+        let material = Arc::new(Coated::new(white, 1.5, 0.2));
+        let r_in = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = hit(material.clone(), true, 1.0);
+
+        // Far from the mirror direction the coat itself contributes next to nothing
+        let scattered = outgoing(1.2, 0.0);
+        let f = material.eval_bsdf(&r_in, &rec, &scattered).r();
+        let expected = (1.0 - fresnel_dielectric(1.0, 1.5)) * f64::cos(1.2) / PI;
+        assert!((f - expected).abs() < 1e-3, "{} {}", f, expected);
+    }
+
+    #[test]
+    fn mix_enters_the_medium_of_the_material_it_scatters_with() {
+        let subsurface = Arc::new(Subsurface::new(
+            Color::new(0.9, 0.9, 0.9),
+            Color::new(1.0, 1.0, 1.0),
+            1.0,
+            0.0,
+        ));
+        let diffuse = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let material = Arc::new(MixMaterial::new(subsurface, diffuse, 0.5));
+        let r_in = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let mut entered = 0;
+        for i in 0..1000 {
+            let mut rec = hit(material.clone(), true, 1.0);
+            rec.p = Point3::new(i as f64 * 0.01, 0.0, 0.0);
+            rec.u = i as f64 / 1000.0;
+
+            // Only the subsurface material scatters specularly, through its boundary
+            let scatter = material.scatter(&r_in, &rec).unwrap();
+            let medium = material.interior_medium(&rec);
+            assert_eq!(medium.is_some(), scatter.is_specular);
+            assert_eq!(material.interior_medium(&rec).is_some(), medium.is_some());
+            if medium.is_some() {
+                entered += 1;
+            }
+        }
+        assert!(entered > 400 && entered < 600, "{}", entered);
+    }
+
+    #[test]
+    fn flat_normal_map_keeps_the_normal() {
+        let inner = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let flat = Arc::new(SolidColor::new(Color::new(0.5, 0.5, 1.0)));
+        let material = NormalMapped::new(inner, flat, 1.0);
+        let rec = hit(
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            true,
+            1.0,
+        );
+        let normal = material.perturb(&rec).normal;
+        assert!((normal - rec.normal).length() < 1e-12);
+    }
+
+    #[test]
+    fn normal_map_tilts_along_the_tangents() {
+        let inner: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let tilted = Arc::new(SolidColor::new(Color::new(1.0, 0.5, 1.0)));
+        let material = NormalMapped::new(inner.clone(), tilted, 1.0);
+
+        // Tilted towards dp/du by 45 degrees
+        let rec = hit(inner.clone(), true, 1.0);
+        let normal = material.perturb(&rec).normal;
+        let expected = Vec3::new(1.0, 0.0, 1.0).unit();
+        assert!((normal - expected).length() < 1e-12);
+
+        // Seen from the back, the map tilts the flipped normal the same way in the plane
+        let mut rec = hit(inner, false, 1.0);
+        rec.normal = -rec.normal;
+        let normal = material.perturb(&rec).normal;
+        assert!(normal.dot(&rec.dpdu) > 0.0 && normal.dot(&rec.normal) > 0.0);
+    }
+
+    #[test]
+    fn bump_map_follows_the_height_gradient() {
+        let inner: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let rec = hit(inner.clone(), true, 1.0);
+
+        let constant = BumpMapped::new(inner.clone(), Arc::new(SolidColor::scalar(0.3)), 1.0);
+        assert!((constant.perturb(&rec).normal - rec.normal).length() < 1e-12);
+
+        // Height rising by 0.5 per unit u leans the normal back against dp/du
+        let slope = BumpMapped::new(inner, Arc::new(UvTexture), 0.5);
+        let expected = Vec3::new(-0.5, 0.0, 1.0).unit();
+        assert!((slope.perturb(&rec).normal - expected).length() < 1e-9);
+    }
+
+    #[test]
+    fn mix_cuts_out_where_it_picks_the_cut_out_material() {
+        let red = Arc::new(Lambertian::new(Color::new(1.0, 0.0, 0.0)));
+        let hole = Arc::new(AlphaMask::new(red, Arc::new(SolidColor::scalar(0.0)), 0.5));
+        let green = Arc::new(Lambertian::new(Color::new(0.0, 1.0, 0.0)));
+        let material = Arc::new(MixMaterial::new(hole, green, 0.3));
+        let r_in = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let mut cut_out = 0;
+        for i in 0..1000 {
+            let mut rec = hit(material.clone(), true, 1.0);
+            rec.p = Point3::new(0.0, i as f64 * 0.01, 0.0);
+            rec.v = i as f64 / 1000.0;
+
+            // Asking twice gives the same answer, and hits that are kept scatter green
+            let is_cutout = material.is_cutout(&rec);
+            assert_eq!(material.is_cutout(&rec), is_cutout);
+            let attenuation = material.scatter(&r_in, &rec).unwrap().attenuation;
+            assert_eq!(attenuation.r() > 0.0, is_cutout);
+            assert_eq!(attenuation.g() > 0.0, !is_cutout);
+            if is_cutout {
+                cut_out += 1;
+            }
+        }
+        assert!(cut_out > 600 && cut_out < 800, "{}", cut_out);
+    }
+
+    #[test]
+    fn charlie_distribution_integrates_to_one() {
+        let base = Arc::new(Lambertian::new(Color::new(0.0, 0.0, 0.0)));
+        for &roughness in &[0.3, 0.6, 1.0] {
+            let material = Sheen::new(base.clone(), Color::new(1.0, 1.0, 1.0), roughness);
+            let area =
+                integrate_hemisphere(|wh| material.distribution(&wh.dir) * cos_theta(&wh.dir));
+            assert!((area - 1.0).abs() < 1e-3, "{} {}", roughness, area);
+        }
+    }
+
+    #[test]
+    fn sheen_does_not_create_energy() {
+        let base = Arc::new(Lambertian::new(Color::new(0.0, 0.0, 0.0)));
+        for &roughness in &[0.3, 1.0] {
+            let material = Arc::new(Sheen::new(
+                base.clone(),
+                Color::new(1.0, 1.0, 1.0),
+                roughness,
+            ));
+            let rec = hit(material.clone(), true, 1.0);
+            for &theta in &[0.0, 0.8, 1.5] {
+                let r_in = Ray::new(outgoing(theta, 0.0).dir, -outgoing(theta, 0.0).dir);
+                let albedo = integrate_hemisphere(|scattered| {
+                    material.eval_bsdf(&r_in, &rec, scattered).r()
+                });
+                assert!(
+                    albedo > 0.0 && albedo <= 1.0,
+                    "{} {} {}",
+                    roughness,
+                    theta,
+                    albedo
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn scattered_rays_keep_the_time_of_the_incoming_ray() {
+        let gray = |x: f64| -> Arc<dyn Texture> { Arc::new(SolidColor::scalar(x)) };
+        let white = Color::new(0.8, 0.8, 0.8);
+        let lambertian: Arc<dyn Material> = Arc::new(Lambertian::new(white));
+        let materials: Vec<Arc<dyn Material>> = vec![
+            lambertian.clone(),
+            Arc::new(OrenNayar::new(white, 0.5)),
+            Arc::new(Metal::new(white, 0.2)),
+            Arc::new(Conductor::new(white, white, 0.3, 0.1)),
+            Arc::new(Dielectric::new(1.5)),
+            Arc::new(Dielectric::new(1.5).with_thin_film(ThinFilm::new(300.0, 1.33))),
+            Arc::new(RoughDielectric::new(1.5, 0.3)),
+            Arc::new(
+                Principled::new(Arc::new(SolidColor::new(white)))
+                    .with_metallic(gray(0.5))
+                    .with_clearcoat(gray(1.0), gray(0.1))
+                    .with_transmission(gray(0.5), 1.5),
+            ),
+            Arc::new(Coated::new(lambertian.clone(), 1.5, 0.1)),
+            Arc::new(Subsurface::new(white, white, 1.4, 0.0)),
+            Arc::new(MixMaterial::new(
+                lambertian.clone(),
+                Arc::new(Metal::new(white, 0.0)),
+                0.5,
+            )),
+            Arc::new(NormalMapped::new(
+                lambertian.clone(),
+                Arc::new(SolidColor::new(Color::new(0.5, 0.5, 1.0))),
+                1.0,
+            )),
+            Arc::new(BumpMapped::new(lambertian.clone(), gray(0.5), 1.0)),
+            Arc::new(AlphaMask::new(lambertian.clone(), gray(1.0), 0.5)),
+            Arc::new(Sheen::new(lambertian, white, 0.5)),
+        ];
+
+        let r_in = Ray::new_at(Point3::new(0.3, 0.0, 1.0), Vec3::new(-0.3, 0.0, -1.0), 0.37);
+        for (i, material) in materials.into_iter().enumerate() {
+            let rec = hit(material.clone(), true, 1.0);
+            let mut scattered = 0;
+            for _ in 0..64 {
+                if let Some(srec) = material.scatter(&r_in, &rec) {
+                    assert_eq!(srec.scattered.time, 0.37, "material {}", i);
+                    scattered += 1;
+                }
+            }
+            assert!(scattered > 0, "material {}", i);
+        }
     }
 }
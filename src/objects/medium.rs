@@ -0,0 +1,176 @@
+use crate::utils::util::random_double;
+use crate::vec::onb::Onb;
+use crate::vec::vec3::{Color, Vec3};
+use std::f64::consts::PI;
+
+// Homogeneous participating medium filling the inside of a closed object.
+// Coefficients are per unit distance and per color channel.
+#[derive(Clone, Copy)]
+pub struct Medium {
+    pub(crate) sigma_a: Color,
+    pub(crate) sigma_s: Color,
+    // Henyey-Greenstein anisotropy, 0 is isotropic and positive is forward scattering
+    pub(crate) g: f64,
+}
+
+// Outcome of tracing a ray segment through the medium
+pub enum MediumEvent {
+    // Scattered at the given distance along the (unit) direction
+    Scatter { distance: f64, weight: Color },
+    // Passed through to the surface at the end of the segment
+    Surface { weight: Color },
+}
+
+impl Medium {
+    pub fn new(sigma_a: Color, sigma_s: Color, g: f64) -> Medium {
+        Medium {
+            sigma_a,
+            sigma_s,
+            g,
+        }
+    }
+
+    fn sigma_t(&self) -> Color {
+        self.sigma_a + self.sigma_s
+    }
+
+    fn transmittance(&self, distance: f64) -> Color {
+        let sigma_t = self.sigma_t();
+        Color::new(
+            (-sigma_t.r() * distance).exp(),
+            (-sigma_t.g() * distance).exp(),
+            (-sigma_t.b() * distance).exp(),
+        )
+    }
+
+    // Samples a free flight distance along a segment of length `max_distance`.
+    // The channel used for sampling is picked uniformly and the weight accounts
+    // for all three channels (spectral MIS), so colored media stay unbiased.
+    pub fn sample(&self, max_distance: f64) -> MediumEvent {
+        let sigma_t = self.sigma_t();
+        let channel = ((random_double() * 3.0) as usize).min(2);
+        let distance = match sigma_t.e[channel] > 0.0 {
+            true => -(1.0 - random_double()).ln() / sigma_t.e[channel],
+            false => f64::INFINITY,
+        };
+
+        if distance < max_distance {
+            let transmittance = self.transmittance(distance);
+            let pdf = (sigma_t * transmittance).e.iter().sum::<f64>() / 3.0;
+            MediumEvent::Scatter {
+                distance,
+                weight: (self.sigma_s * transmittance) / pdf,
+            }
+        } else {
+            let transmittance = self.transmittance(max_distance);
+            let pdf = transmittance.e.iter().sum::<f64>() / 3.0;
+            MediumEvent::Surface {
+                weight: transmittance / pdf,
+            }
+        }
+    }
+
+    // Samples a new direction from the Henyey-Greenstein phase function around the
+    // propagation direction `dir`. Perfect importance sampling, so the weight is one.
+    pub fn sample_phase(&self, dir: &Vec3) -> Vec3 {
+        let xi = random_double();
+        let cos_theta = match self.g.abs() < 1e-3 {
+            true => 1.0 - 2.0 * xi,
+            false => {
+                let g = self.g;
+                let sqr_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+                (1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)
+            }
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_double();
+        let frame = Onb::build_from_w(dir);
+        frame.local_to_world(&Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gray_medium_scatters_with_its_extinction_probability() {
+        let medium = Medium::new(Color::new(0.5, 0.5, 0.5), Color::new(1.5, 1.5, 1.5), 0.0);
+        let samples = 100_000;
+        let mut scattered = 0;
+        for _ in 0..samples {
+            match medium.sample(0.5) {
+                MediumEvent::Scatter { distance, weight } => {
+                    assert!(distance < 0.5);
+                    assert!((weight.r() - 0.75).abs() < 1e-12);
+                    scattered += 1;
+                }
+                MediumEvent::Surface { weight } => assert!((weight.r() - 1.0).abs() < 1e-12),
+            }
+        }
+        let fraction = scattered as f64 / samples as f64;
+        let expected = 1.0 - (-2.0 * 0.5f64).exp();
+        assert!(
+            (fraction - expected).abs() < 0.01,
+            "{} {}",
+            fraction,
+            expected
+        );
+    }
+
+    #[test]
+    fn colored_medium_transmits_unbiased() {
+        let medium = Medium::new(Color::new(0.0, 1.0, 4.0), Color::new(0.5, 0.5, 0.5), 0.0);
+        let samples = 200_000;
+        let mut transmitted = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            if let MediumEvent::Surface { weight } = medium.sample(0.5) {
+                transmitted = transmitted + weight / samples as f64;
+            }
+        }
+        let expected = medium.transmittance(0.5);
+        assert!((transmitted.r() - expected.r()).abs() < 0.01);
+        assert!((transmitted.g() - expected.g()).abs() < 0.01);
+        assert!((transmitted.b() - expected.b()).abs() < 0.01);
+    }
+
+    #[test]
+    fn phase_samples_follow_henyey_greenstein() {
+        let dir = Vec3::new(1.0, 2.0, -0.5).unit();
+        for &g in &[0.0, 0.6, -0.3] {
+            let medium = Medium::new(Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0), g);
+
+            // Histogram over the cosine to the propagation direction
+            let bins = 10;
+            let samples = 200_000;
+            let mut histogram = vec![0.0; bins];
+            let mut mean_cos = 0.0;
+            for _ in 0..samples {
+                let cos = medium.sample_phase(&dir).dot(&dir);
+                mean_cos += cos / samples as f64;
+                let bin = (((cos + 1.0) / 2.0 * bins as f64) as usize).min(bins - 1);
+                histogram[bin] += 1.0 / samples as f64;
+            }
+            assert!((mean_cos - g).abs() < 0.01, "{} {}", g, mean_cos);
+
+            // Closed form CDF of HG over the cosine
+            let cdf = |cos: f64| match g == 0.0 {
+                true => (cos + 1.0) / 2.0,
+                false => {
+                    (1.0 - g * g) / (2.0 * g)
+                        * (1.0 / (1.0 + g * g - 2.0 * g * cos).sqrt() - 1.0 / (1.0 + g))
+                }
+            };
+            for (bin, frequency) in histogram.iter().enumerate() {
+                let lo = -1.0 + 2.0 * bin as f64 / bins as f64;
+                let hi = -1.0 + 2.0 * (bin + 1) as f64 / bins as f64;
+                let expected = cdf(hi) - cdf(lo);
+                assert!((frequency - expected).abs() < 0.005, "{} {}", g, bin);
+            }
+        }
+    }
+}
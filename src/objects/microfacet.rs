@@ -0,0 +1,359 @@
+use crate::utils::complex::Complex;
+use crate::utils::util::{clamp, random_double};
+use crate::vec::vec3::{Color, Vec3};
+use std::f64::consts::PI;
+
+// All directions in here live in the local shading frame, where the surface normal is +z.
+
+pub fn cos_theta(w: &Vec3) -> f64 {
+    w.z()
+}
+
+fn cos2_theta(w: &Vec3) -> f64 {
+    w.z() * w.z()
+}
+
+fn sin2_theta(w: &Vec3) -> f64 {
+    (1.0 - cos2_theta(w)).max(0.0)
+}
+
+fn tan2_theta(w: &Vec3) -> f64 {
+    sin2_theta(w) / cos2_theta(w)
+}
+
+fn cos_phi(w: &Vec3) -> f64 {
+    let sin_theta = sin2_theta(w).sqrt();
+    match sin_theta == 0.0 {
+        true => 1.0,
+        false => clamp(w.x() / sin_theta, -1.0, 1.0),
+    }
+}
+
+fn sin_phi(w: &Vec3) -> f64 {
+    let sin_theta = sin2_theta(w).sqrt();
+    match sin_theta == 0.0 {
+        true => 0.0,
+        false => clamp(w.y() / sin_theta, -1.0, 1.0),
+    }
+}
+
+// Mirror `wo` about the microfacet normal `wm`
+pub fn reflect(wo: &Vec3, wm: &Vec3) -> Vec3 {
+    -*wo + 2.0 * wo.dot(wm) * *wm
+}
+
+// Refracts `wi` through the microfacet normal `wm`, where `eta` is the relative index
+// of refraction n_t / n_i. Returns None on total internal reflection.
+pub fn refract(wi: &Vec3, wm: &Vec3, eta: f64) -> Option<Vec3> {
+    let mut n = *wm;
+    let mut eta = eta;
+    let mut cos_theta_i = n.dot(wi);
+    if cos_theta_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+        n = -n;
+    }
+
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-*wi / eta + (cos_theta_i / eta - cos_theta_t) * n)
+}
+
+// Generalized half vector of a refraction from `wo` into `wi` with relative IOR `eta`,
+// along with the denominator of the transmission Jacobian. None for back facing microfacets.
+pub fn transmission_half_vector(wo: &Vec3, wi: &Vec3, eta: f64) -> Option<(Vec3, f64)> {
+    let mut wm = (eta * *wi + *wo).unit();
+    if cos_theta(&wm) < 0.0 {
+        wm = -wm;
+    }
+    if wm.dot(wi) * cos_theta(wi) < 0.0 || wm.dot(wo) * cos_theta(wo) < 0.0 {
+        return None;
+    }
+    let denom = wi.dot(&wm) + wo.dot(&wm) / eta;
+    Some((wm, denom))
+}
+
+// Maps a perceptual roughness in [0,1] to the GGX alpha parameter.
+pub fn roughness_to_alpha(roughness: f64) -> f64 {
+    (roughness * roughness).max(1e-4)
+}
+
+// ----------------------------------------------------------------------
+// ----- TROWBRIDGE-REITZ (GGX) -----
+// ----------------------------------------------------------------------
+#[derive(Clone, Copy)]
+pub struct TrowbridgeReitz {
+    pub(crate) alpha_x: f64,
+    pub(crate) alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> TrowbridgeReitz {
+        TrowbridgeReitz {
+            alpha_x: alpha_x.max(1e-4),
+            alpha_y: alpha_y.max(1e-4),
+        }
+    }
+
+    // Distribution of microfacet normals
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        let tan2 = tan2_theta(wm);
+        if tan2.is_infinite() || tan2.is_nan() {
+            return 0.0;
+        }
+        let cos4 = cos2_theta(wm) * cos2_theta(wm);
+        let e = tan2
+            * ((cos_phi(wm) / self.alpha_x).powi(2) + (sin_phi(wm) / self.alpha_y).powi(2));
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos4 * (1.0 + e) * (1.0 + e))
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let tan2 = tan2_theta(w);
+        if tan2.is_infinite() || tan2.is_nan() {
+            return 0.0;
+        }
+        let alpha2 = (cos_phi(w) * self.alpha_x).powi(2) + (sin_phi(w) * self.alpha_y).powi(2);
+        ((1.0 + alpha2 * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    // Smith masking function
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Height-correlated Smith masking-shadowing function
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Distribution of normals visible from `w`
+    pub fn visible_d(&self, w: &Vec3, wm: &Vec3) -> f64 {
+        self.g1(w) / cos_theta(w).abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    // Samples a microfacet normal from the visible normal distribution (Heitz 2018).
+    pub fn sample_wm(&self, w: &Vec3) -> Vec3 {
+        // Transform `w` to the hemispherical configuration
+        let mut wh = Vec3::new(self.alpha_x * w.x(), self.alpha_y * w.y(), w.z()).unit();
+        if wh.z() < 0.0 {
+            wh = -wh;
+        }
+
+        // Orthonormal basis for the visible normal sampling
+        let t1 = match wh.z() < 0.99999 {
+            true => Vec3::new(0.0, 0.0, 1.0).cross(wh).unit(),
+            false => Vec3::new(1.0, 0.0, 0.0),
+        };
+        let t2 = wh.cross(t1);
+
+        // Uniformly distributed point on the unit disk, warped to the visible half
+        let r = random_double().sqrt();
+        let phi = 2.0 * PI * random_double();
+        let px = r * phi.cos();
+        let mut py = r * phi.sin();
+        let h = (1.0 - px * px).sqrt();
+        let s = (1.0 + wh.z()) / 2.0;
+        py = (1.0 - s) * h + s * py;
+
+        // Reproject onto the hemisphere and back to the ellipsoid configuration
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+        let nh = px * t1 + py * t2 + pz * wh;
+        Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        )
+        .unit()
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- FRESNEL -----
+// ----------------------------------------------------------------------
+
+// (1 - cos)^5 term shared by Schlick's approximation and the Disney lobes
+pub fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - clamp(cos_theta, 0.0, 1.0)).powi(5)
+}
+
+pub fn fresnel_schlick(f0: &Color, cos_theta: f64) -> Color {
+    let w = schlick_weight(cos_theta);
+    *f0 + w * (Color::new(1.0, 1.0, 1.0) - *f0)
+}
+
+// Unpolarized Fresnel reflectance of a dielectric interface with relative IOR `eta`.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let mut cos_theta_i = clamp(cos_theta_i, -1.0, 1.0);
+    let mut eta = eta;
+    if cos_theta_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+    }
+
+    let sin2_theta_i = 1.0 - cos_theta_i * cos_theta_i;
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let r_parl = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perp = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+// Fresnel reflectance of a conductor with complex index of refraction `eta + i*k`.
+pub fn fresnel_complex(cos_theta_i: f64, eta: Complex) -> f64 {
+    let cos_theta_i = clamp(cos_theta_i, 0.0, 1.0);
+    let sin2_theta_i = 1.0 - cos_theta_i * cos_theta_i;
+    let sin2_theta_t = Complex::real(sin2_theta_i) / (eta * eta);
+    let cos_theta_t = (Complex::real(1.0) - sin2_theta_t).sqrt();
+
+    let cos_i = Complex::real(cos_theta_i);
+    let r_parl = (eta * cos_i - cos_theta_t) / (eta * cos_i + cos_theta_t);
+    let r_perp = (cos_i - eta * cos_theta_t) / (cos_i + eta * cos_theta_t);
+    (r_parl.norm() + r_perp.norm()) / 2.0
+}
+
+// Per channel conductor Fresnel reflectance
+pub fn fresnel_complex_color(cos_theta_i: f64, eta: &Color, k: &Color) -> Color {
+    Color::new(
+        fresnel_complex(cos_theta_i, Complex::new(eta.r(), k.r())),
+        fresnel_complex(cos_theta_i, Complex::new(eta.g(), k.g())),
+        fresnel_complex(cos_theta_i, Complex::new(eta.b(), k.b())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spherical_direction(theta: f64, phi: f64) -> Vec3 {
+        Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        )
+    }
+
+    // Midpoint rule over the upper hemisphere
+    fn integrate_hemisphere<F: Fn(&Vec3) -> f64>(f: F) -> f64 {
+        let (n_theta, n_phi) = (1000, 200);
+        let d_theta = PI / 2.0 / n_theta as f64;
+        let d_phi = 2.0 * PI / n_phi as f64;
+        let mut sum = 0.0;
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                sum += f(&spherical_direction(theta, phi)) * theta.sin() * d_theta * d_phi;
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn projected_normal_distribution_integrates_to_one() {
+        for &(ax, ay) in &[(0.5, 0.5), (0.3, 0.8), (1.0, 1.0)] {
+            let distribution = TrowbridgeReitz::new(ax, ay);
+            let area = integrate_hemisphere(|wm| distribution.d(wm) * cos_theta(wm));
+            assert!((area - 1.0).abs() < 1e-3, "{} {} {}", ax, ay, area);
+        }
+    }
+
+    // Only normals facing `w` are visible from it
+    fn facing_visible_d(distribution: &TrowbridgeReitz, w: &Vec3, wm: &Vec3) -> f64 {
+        match w.dot(wm) > 0.0 {
+            true => distribution.visible_d(w, wm),
+            false => 0.0,
+        }
+    }
+
+    #[test]
+    fn visible_normal_distribution_integrates_to_one() {
+        let distribution = TrowbridgeReitz::new(0.4, 0.7);
+        for &theta in &[0.0, 0.7, 1.3] {
+            let w = spherical_direction(theta, 0.4);
+            let area = integrate_hemisphere(|wm| facing_visible_d(&distribution, &w, wm));
+            assert!((area - 1.0).abs() < 1e-3, "{} {}", theta, area);
+        }
+    }
+
+    #[test]
+    fn masking_shadowing_stays_in_range() {
+        let distribution = TrowbridgeReitz::new(0.6, 0.6);
+        let wo = spherical_direction(1.2, 0.0);
+        let wi = spherical_direction(0.5, 2.0);
+        assert!((distribution.g1(&Vec3::new(0.0, 0.0, 1.0)) - 1.0).abs() < 1e-12);
+        assert!(distribution.g(&wo, &wi) <= distribution.g1(&wo).min(distribution.g1(&wi)));
+        assert!(distribution.g(&wo, &wi) >= distribution.g1(&wo) * distribution.g1(&wi));
+    }
+
+    #[test]
+    fn sampled_visible_normals_follow_their_pdf() {
+        let distribution = TrowbridgeReitz::new(0.5, 0.5);
+        let w = spherical_direction(0.8, 0.0);
+
+        // Histogram of the sampled normals over bands of constant theta
+        let bins = 8;
+        let samples = 200_000;
+        let mut histogram = vec![0.0; bins];
+        for _ in 0..samples {
+            let wm = distribution.sample_wm(&w);
+            let theta = clamp(cos_theta(&wm), 0.0, 1.0).acos();
+            let bin = ((theta / (PI / 2.0) * bins as f64) as usize).min(bins - 1);
+            histogram[bin] += 1.0 / samples as f64;
+        }
+
+        for (bin, &frequency) in histogram.iter().enumerate() {
+            let (lo, hi) = (bin as f64 / bins as f64, (bin + 1) as f64 / bins as f64);
+            let expected = integrate_hemisphere(|wm| {
+                let theta = cos_theta(wm).acos() / (PI / 2.0);
+                match theta >= lo && theta < hi {
+                    true => facing_visible_d(&distribution, &w, wm),
+                    false => 0.0,
+                }
+            });
+            assert!(
+                (frequency - expected).abs() < 0.005,
+                "bin {}: {} vs {}",
+                bin,
+                frequency,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn complex_fresnel_at_normal_incidence() {
+        // ((n - 1)^2 + k^2) / ((n + 1)^2 + k^2)
+        for &(n, k) in &[(0.143, 3.983), (1.657, 9.224), (1.5, 0.0)] {
+            let expected = ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+            let r = fresnel_complex(1.0, Complex::new(n, k));
+            assert!((r - expected).abs() < 1e-9, "{} {} {}", n, k, r);
+        }
+
+        // Gold reflects far more red than blue
+        let gold = fresnel_complex_color(
+            1.0,
+            &Color::new(0.143, 0.374, 1.442),
+            &Color::new(3.983, 2.385, 1.603),
+        );
+        assert!((gold.r() - 0.9667).abs() < 1e-3);
+        assert!(gold.r() > gold.g() && gold.g() > gold.b());
+    }
+
+    #[test]
+    fn complex_fresnel_without_extinction_matches_dielectric() {
+        for &cos in &[1.0, 0.8, 0.5, 0.2, 0.05] {
+            let conductor = fresnel_complex(cos, Complex::real(1.5));
+            let dielectric = fresnel_dielectric(cos, 1.5);
+            assert!((conductor - dielectric).abs() < 1e-9, "{}", cos);
+        }
+        assert_eq!(fresnel_dielectric(0.1, 1.0 / 1.5), 1.0);
+    }
+}
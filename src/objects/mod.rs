@@ -1,3 +1,8 @@
 pub mod hittable;
 pub mod material;
+pub mod medium;
+pub mod microfacet;
+pub mod quad;
 pub mod sphere;
+pub mod texture;
+pub mod thin_film;
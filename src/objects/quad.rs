@@ -0,0 +1,339 @@
+use crate::lights::light::{Light, LightSample};
+use crate::lights::light_bvh::LightBounds;
+use crate::objects::hittable::{HitRecord, Hittable};
+use crate::objects::material::Material;
+use crate::utils::util::{clamp, random_double};
+use crate::vec::vec3::{Color, Point3, Ray, Vec3};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// Below this solid angle, spherical rectangles are too thin to sample reliably and
+// points are picked uniformly by area instead
+const MIN_SPHERICAL_SAMPLE_SOLID_ANGLE: f64 = 1e-3;
+
+// Parallelogram spanned by the edges `u` and `v` from the corner `q`
+#[derive(Clone)]
+pub struct Quad {
+    pub(crate) q: Point3,
+    pub(crate) u: Vec3,
+    pub(crate) v: Vec3,
+    pub(crate) material: Arc<dyn Material>,
+    // Index into the scene lights when the quad is registered as an area light
+    pub(crate) light: Option<usize>,
+    normal: Vec3,
+    // Plane offset along the normal
+    d: f64,
+    // Projects a point in the plane onto the (u, v) coordinates of the quad
+    w: Vec3,
+}
+
+// Spherical rectangle a rectangular quad covers as seen from a point (Ureña et al.
+// 2013, "An Area-Preserving Parametrization for Spherical Rectangles"), in a frame
+// with x and y along the edges and the rectangle at z = z0 < 0.
+struct SphericalRectangle {
+    x: Vec3,
+    y: Vec3,
+    z: Vec3,
+    x0: f64,
+    x1: f64,
+    y0: f64,
+    y1: f64,
+    z0: f64,
+    b0: f64,
+    b1: f64,
+    k: f64,
+    solid_angle: f64,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Quad {
+        let n = u.cross(v);
+        let normal = n.unit();
+        Quad {
+            q,
+            u,
+            v,
+            material,
+            light: None,
+            normal,
+            d: normal.dot(&q),
+            w: n / n.dot(&n),
+        }
+    }
+
+    fn area(&self) -> f64 {
+        self.u.cross(self.v).length()
+    }
+
+    // Light emitted at the surface point with quad coordinates (a, b), seen by a ray
+    // travelling along `dir`
+    fn emission_at(&self, a: f64, b: f64, dir: &Vec3) -> Color {
+        let p = self.q + a * self.u + b * self.v;
+        let mut hit_record = HitRecord::new(
+            p,
+            self.normal,
+            Arc::clone(&self.material),
+            0.0,
+            a,
+            b,
+            true,
+        );
+        hit_record.set_face_normal(Ray::new(p - *dir, *dir), self.normal);
+        hit_record.set_tangents(self.u, self.v);
+        self.material.emitted(&hit_record)
+    }
+
+    fn spherical_rectangle(&self, p: &Point3) -> Option<SphericalRectangle> {
+        // Only rectangles have an exact spherical parametrization
+        let (exl, eyl) = (self.u.length(), self.v.length());
+        let x = self.u / exl;
+        let y = self.v / eyl;
+        if x.dot(&y).abs() > 1e-6 {
+            return None;
+        }
+        let mut z = x.cross(y);
+
+        let d = self.q - *p;
+        let (x0, y0, mut z0) = (d.dot(&x), d.dot(&y), d.dot(&z));
+        if z0.abs() < 1e-12 {
+            return None;
+        }
+        if z0 > 0.0 {
+            z0 = -z0;
+            z = -z;
+        }
+        let (x1, y1) = (x0 + exl, y0 + eyl);
+
+        // Normals of the planes through p and the four edges
+        let v00 = Vec3::new(x0, y0, z0);
+        let v01 = Vec3::new(x0, y1, z0);
+        let v10 = Vec3::new(x1, y0, z0);
+        let v11 = Vec3::new(x1, y1, z0);
+        let n0 = v00.cross(v10).unit();
+        let n1 = v10.cross(v11).unit();
+        let n2 = v11.cross(v01).unit();
+        let n3 = v01.cross(v00).unit();
+
+        let angle = |a: &Vec3, b: &Vec3| clamp(-a.dot(b), -1.0, 1.0).acos();
+        let g0 = angle(&n0, &n1);
+        let g1 = angle(&n1, &n2);
+        let g2 = angle(&n2, &n3);
+        let g3 = angle(&n3, &n0);
+        let solid_angle = g0 + g1 + g2 + g3 - 2.0 * PI;
+        if solid_angle <= MIN_SPHERICAL_SAMPLE_SOLID_ANGLE {
+            return None;
+        }
+
+        Some(SphericalRectangle {
+            x,
+            y,
+            z,
+            x0,
+            x1,
+            y0,
+            y1,
+            z0,
+            b0: n0.z(),
+            b1: n2.z(),
+            k: 2.0 * PI - g2 - g3,
+            solid_angle,
+        })
+    }
+
+    // Uniform point on the quad, converted to a solid angle density at `p`
+    fn sample_area(&self, p: &Point3) -> Option<LightSample> {
+        let (a, b) = (random_double(), random_double());
+        let to_light = self.q + a * self.u + b * self.v - *p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        let wi = to_light / distance;
+        let cos_light = self.normal.dot(&wi).abs();
+        if cos_light <= 0.0 {
+            return None;
+        }
+
+        let pdf = distance_squared / (cos_light * self.area());
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: self.emission_at(a, b, &wi) / pdf,
+            pdf: Some(pdf),
+        })
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // No hit if the ray runs parallel to the plane
+        let denom = self.normal.dot(&ray.dir);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.d - self.normal.dot(&ray.origin)) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+
+        // Position inside the quad in terms of its edges
+        let p = ray.at(t);
+        let planar = p - self.q;
+        let a = self.w.dot(&planar.cross(self.v));
+        let b = self.w.dot(&self.u.cross(planar));
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return None;
+        }
+
+        let mut hit_record = HitRecord::new(
+            p,
+            self.normal,
+            Arc::clone(&self.material),
+            t,
+            a,
+            b,
+            true,
+        );
+        hit_record.set_face_normal(ray, self.normal);
+        hit_record.set_tangents(self.u, self.v);
+        hit_record.light = self.light;
+
+        // Rays pass through cut out parts of the surface on to the far side
+        if self.material.is_cutout(&hit_record) {
+            return None;
+        }
+        Some(hit_record)
+    }
+}
+
+// Emissive quads as area lights. Rectangles are sampled uniformly by the solid angle
+// they subtend, other parallelograms uniformly by area.
+impl Light for Quad {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let rect = match self.spherical_rectangle(p) {
+            Some(rect) => rect,
+            None => return self.sample_area(p),
+        };
+
+        // Pick the sub-area along x first, then the height along y
+        let au = random_double() * rect.solid_angle + rect.k;
+        let fu = (au.cos() * rect.b0 - rect.b1) / au.sin();
+        let cu = clamp(
+            (1.0 / (fu * fu + rect.b0 * rect.b0).sqrt()).copysign(fu),
+            -1.0 + 1e-12,
+            1.0 - 1e-12,
+        );
+        let xu = clamp(-(cu * rect.z0) / (1.0 - cu * cu).sqrt(), rect.x0, rect.x1);
+        let dd = (xu * xu + rect.z0 * rect.z0).sqrt();
+        let h0 = rect.y0 / (dd * dd + rect.y0 * rect.y0).sqrt();
+        let h1 = rect.y1 / (dd * dd + rect.y1 * rect.y1).sqrt();
+        let hv = h0 + random_double() * (h1 - h0);
+        let yv = match hv * hv < 1.0 - 1e-9 {
+            true => hv * dd / (1.0 - hv * hv).sqrt(),
+            false => rect.y1,
+        };
+
+        let to_light = xu * rect.x + yv * rect.y + rect.z0 * rect.z;
+        let distance = to_light.length();
+        let wi = to_light / distance;
+        let a = clamp((xu - rect.x0) / (rect.x1 - rect.x0), 0.0, 1.0);
+        let b = clamp((yv - rect.y0) / (rect.y1 - rect.y0), 0.0, 1.0);
+
+        let pdf = 1.0 / rect.solid_angle;
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: self.emission_at(a, b, &wi) / pdf,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn pdf_li(&self, p: &Point3, wi: &Vec3) -> f64 {
+        let ray = Ray::new(*p, wi.unit());
+        let t = match self.hit(ray, 0.0, f64::INFINITY) {
+            Some(hit_record) => hit_record.t,
+            None => return 0.0,
+        };
+        match self.spherical_rectangle(p) {
+            Some(rect) => 1.0 / rect.solid_angle,
+            None => {
+                let cos_light = self.normal.dot(&ray.dir).abs();
+                match cos_light > 0.0 {
+                    true => t * t / (cos_light * self.area()),
+                    false => 0.0,
+                }
+            }
+        }
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let corners = [
+            self.q,
+            self.q + self.u,
+            self.q + self.v,
+            self.q + self.u + self.v,
+        ];
+        let min = corners.iter().fold(corners[0], |m, c| {
+            Vec3::new(m.x().min(c.x()), m.y().min(c.y()), m.z().min(c.z()))
+        });
+        let max = corners.iter().fold(corners[0], |m, c| {
+            Vec3::new(m.x().max(c.x()), m.y().max(c.y()), m.z().max(c.z()))
+        });
+
+        // Emission of materials without a facing check reaches both sides
+        let center_emission = self.emission_at(0.5, 0.5, &-self.normal).luminance();
+        let back_emission = self.emission_at(0.5, 0.5, &self.normal).luminance();
+        let two_sided = back_emission > 0.0;
+        let phi = PI * self.area() * (center_emission + back_emission);
+        Some(LightBounds::new(
+            min,
+            max,
+            phi,
+            match center_emission > 0.0 {
+                true => self.normal,
+                false => -self.normal,
+            },
+            1.0,
+            0.0,
+            two_sided,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::material::DiffuseLight;
+
+    // Samples the light from `p` and checks every sample against `pdf_li`
+    fn assert_sampled_pdf_matches(light: &dyn Light, p: &Point3) {
+        for _ in 0..10_000 {
+            let sample = light.sample_li(p).unwrap();
+            let pdf = sample.pdf.unwrap();
+            let pdf_li = light.pdf_li(p, &sample.wi);
+            assert!((pdf - pdf_li).abs() < 1e-6 * pdf, "{} {}", pdf, pdf_li);
+        }
+    }
+
+    fn quad(u: Vec3, v: Vec3) -> Quad {
+        let light = DiffuseLight::new(Color::new(4.0, 4.0, 4.0));
+        Quad::new(Point3::new(-1.0, 3.0, -1.0), u, v, Arc::new(light))
+    }
+
+    #[test]
+    fn sampled_pdf_matches_pdf_li() {
+        // Rectangles by solid angle, up close and from below an edge
+        let rectangle = quad(Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 3.0));
+        assert!(rectangle
+            .spherical_rectangle(&Point3::new(0.0, 0.0, 0.0))
+            .is_some());
+        assert_sampled_pdf_matches(&rectangle, &Point3::new(0.0, 0.0, 0.0));
+        assert_sampled_pdf_matches(&rectangle, &Point3::new(3.0, 2.5, 4.0));
+
+        // Tiny rectangles and parallelograms by area
+        assert!(rectangle
+            .spherical_rectangle(&Point3::new(0.0, -500.0, 0.0))
+            .is_none());
+        assert_sampled_pdf_matches(&rectangle, &Point3::new(0.0, -500.0, 0.0));
+        let parallelogram = quad(Vec3::new(2.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 2.0));
+        assert_sampled_pdf_matches(&parallelogram, &Point3::new(0.5, 1.0, 0.0));
+    }
+}
@@ -1,12 +1,26 @@
+use crate::lights::light::{Light, LightSample};
+use crate::lights::light_bvh::LightBounds;
 use crate::objects::hittable::{HitRecord, Hittable};
 use crate::objects::material::Material;
-use crate::vec::vec3::{Point3, Ray};
+use crate::utils::util::random_double;
+use crate::vec::onb::Onb;
+use crate::vec::vec3::{Color, Point3, Ray, Vec3};
+use std::f64::consts::PI;
 use std::sync::Arc;
 
+// Below sin^2 of about 1.5 degrees, cones are sampled with a small angle approximation
+const SMALL_CONE_SIN2: f64 = 0.00068523;
+
+#[derive(Clone)]
 pub struct Sphere {
     pub(crate) center: Point3,
     pub(crate) radius: f64,
     pub(crate) material: Arc<dyn Material>,
+    // Index into the scene lights when the sphere is registered as an area light
+    pub(crate) light: Option<usize>,
+    // Distance moved per second while the shutter is open. Only spheres at rest can be
+    // area lights.
+    pub(crate) velocity: Vec3,
 }
 
 impl Sphere {
@@ -15,13 +29,48 @@ impl Sphere {
             center,
             radius,
             material,
+            light: None,
+            velocity: Vec3::new(0.0, 0.0, 0.0),
         }
     }
+
+    // Moving sphere, blurred over the shutter time of the camera
+    pub fn with_velocity(mut self, velocity: Vec3) -> Sphere {
+        self.velocity = velocity;
+        self
+    }
+
+    fn center_at(&self, time: f64) -> Point3 {
+        self.center + time * self.velocity
+    }
+
+    // Maps a point on the unit sphere to (u, v) texture coordinates in [0,1],
+    // with u running around the y axis starting at -x and v from bottom to top.
+    fn get_sphere_uv(p: &Point3) -> (f64, f64) {
+        let theta = (-p.y()).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    // Partial derivatives of the surface point along u and v, for the point `n` on the
+    // unit sphere.
+    fn get_sphere_tangents(&self, n: &Vec3) -> (Vec3, Vec3) {
+        let dpdu = (2.0 * PI * self.radius) * Vec3::new(n.z(), 0.0, -n.x());
+        let sin_theta = (1.0 - n.y() * n.y()).max(1e-8).sqrt();
+        let dpdv = (PI * self.radius)
+            * Vec3::new(
+                -n.x() * n.y() / sin_theta,
+                sin_theta,
+                -n.y() * n.z() / sin_theta,
+            );
+        (dpdu, dpdv)
+    }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = ray.origin - self.center;
+        let center = self.center_at(ray.time);
+        let oc = ray.origin - center;
         let a = ray.dir.length_squared();
         let half_b = oc.dot(&ray.dir);
         let c = oc.length_squared() - self.radius * self.radius;
@@ -33,10 +82,26 @@ impl Hittable for Sphere {
                 if t < &t_max && t > &t_min {
                     let t = *t;
                     let p = ray.at(t);
-                    let outward_normal = (p - self.center) / self.radius;
-                    let mut hit_record =
-                        HitRecord::new(p, outward_normal, Arc::clone(&self.material), t, true);
+                    let outward_normal = (p - center) / self.radius;
+                    let (u, v) = Sphere::get_sphere_uv(&outward_normal);
+                    let mut hit_record = HitRecord::new(
+                        p,
+                        outward_normal,
+                        Arc::clone(&self.material),
+                        t,
+                        u,
+                        v,
+                        true,
+                    );
                     hit_record.set_face_normal(ray, outward_normal);
+                    let (dpdu, dpdv) = self.get_sphere_tangents(&outward_normal);
+                    hit_record.set_tangents(dpdu, dpdv);
+                    hit_record.light = self.light;
+
+                    // Rays pass through cut out parts of the surface on to the far side
+                    if self.material.is_cutout(&hit_record) {
+                        continue;
+                    }
                     return Some(hit_record);
                 }
             }
@@ -44,3 +109,189 @@ impl Hittable for Sphere {
         return None;
     }
 }
+
+impl Sphere {
+    // Light emitted at the surface point with outward normal `n`, seen by a ray
+    // travelling along `dir`
+    fn emission_at(&self, n: &Vec3, dir: &Vec3) -> Color {
+        let (u, v) = Sphere::get_sphere_uv(n);
+        let mut hit_record = HitRecord::new(
+            self.center + self.radius * *n,
+            *n,
+            Arc::clone(&self.material),
+            0.0,
+            u,
+            v,
+            true,
+        );
+        hit_record.set_face_normal(Ray::new(hit_record.p - *dir, *dir), *n);
+        let (dpdu, dpdv) = self.get_sphere_tangents(n);
+        hit_record.set_tangents(dpdu, dpdv);
+        self.material.emitted(&hit_record)
+    }
+
+    // 1 - cos(theta_max) of the cone, without cancellation for tiny cones
+    fn one_minus_cos_theta_max(sin2_theta_max: f64) -> f64 {
+        match sin2_theta_max < SMALL_CONE_SIN2 {
+            true => 0.5 * sin2_theta_max,
+            false => 1.0 - (1.0 - sin2_theta_max).max(0.0).sqrt(),
+        }
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    // Uniform point on the surface, for shading points inside the sphere
+    fn sample_area(&self, p: &Point3) -> Option<LightSample> {
+        let n = Vec3::random_unit_vector();
+        let to_light = self.center + self.radius * n - *p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        let wi = to_light / distance;
+        let cos_light = n.dot(&wi).abs();
+        if cos_light <= 0.0 {
+            return None;
+        }
+
+        // Convert the area density to solid angle
+        let pdf = distance_squared / (cos_light * self.area());
+        let radiance = self.emission_at(&n, &wi);
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: radiance / pdf,
+            pdf: Some(pdf),
+        })
+    }
+}
+
+// Emissive spheres as area lights. From outside, directions are sampled uniformly
+// inside the cone the sphere subtends; from inside, points uniformly by area.
+impl Light for Sphere {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let to_center = self.center - *p;
+        let distance_center_squared = to_center.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_center_squared <= radius_squared {
+            return self.sample_area(p);
+        }
+
+        // Direction within the cone, then the point where it first meets the sphere
+        let distance_center = distance_center_squared.sqrt();
+        let sin2_theta_max = radius_squared / distance_center_squared;
+        let one_minus_cos_theta_max = Sphere::one_minus_cos_theta_max(sin2_theta_max);
+        let xi = random_double();
+        let (cos_theta, sin2_theta) = match sin2_theta_max < SMALL_CONE_SIN2 {
+            // Small angle approximation for far away spheres
+            true => (1.0 - 0.5 * sin2_theta_max * xi, sin2_theta_max * xi),
+            false => {
+                let cos_theta = 1.0 - xi * one_minus_cos_theta_max;
+                (cos_theta, 1.0 - cos_theta * cos_theta)
+            }
+        };
+        let phi = 2.0 * PI * random_double();
+
+        let distance = distance_center * cos_theta
+            - (radius_squared - distance_center_squared * sin2_theta)
+                .max(0.0)
+                .sqrt();
+        let frame = Onb::build_from_w(&to_center);
+        let sin_theta = sin2_theta.max(0.0).sqrt();
+        let wi = frame.local_to_world(&Vec3::new(
+            phi.cos() * sin_theta,
+            phi.sin() * sin_theta,
+            cos_theta,
+        ));
+        let n = (*p + distance * wi - self.center).unit();
+
+        let pdf = 1.0 / (2.0 * PI * one_minus_cos_theta_max);
+        let radiance = self.emission_at(&n, &wi);
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: radiance / pdf,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn pdf_li(&self, p: &Point3, wi: &Vec3) -> f64 {
+        let ray = Ray::new(*p, wi.unit());
+        let t = match self.hit(ray, 0.0, f64::INFINITY) {
+            Some(hit_record) => hit_record.t,
+            None => return 0.0,
+        };
+
+        let distance_center_squared = (self.center - *p).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_center_squared <= radius_squared {
+            let n = (ray.at(t) - self.center) / self.radius;
+            let cos_light = n.dot(&ray.dir).abs();
+            return match cos_light > 0.0 {
+                true => t * t / (cos_light * self.area()),
+                false => 0.0,
+            };
+        }
+        let sin2_theta_max = radius_squared / distance_center_squared;
+        1.0 / (2.0 * PI * Sphere::one_minus_cos_theta_max(sin2_theta_max))
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // Power of a uniform emitter, estimated from the emission at the top
+        let top = Vec3::new(0.0, 1.0, 0.0);
+        let phi = PI * self.area() * self.emission_at(&top, &-top).luminance();
+        let extent = Vec3::new(self.radius, self.radius, self.radius);
+        Some(LightBounds::new(
+            self.center - extent,
+            self.center + extent,
+            phi,
+            Vec3::new(0.0, 0.0, 1.0),
+            -1.0,
+            0.0,
+            false,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::material::DiffuseLight;
+
+    // Samples the light from `p` and checks every sample against `pdf_li`
+    fn assert_sampled_pdf_matches(light: &dyn Light, p: &Point3) {
+        for _ in 0..10_000 {
+            let sample = light.sample_li(p).unwrap();
+            let pdf = sample.pdf.unwrap();
+            let pdf_li = light.pdf_li(p, &sample.wi);
+            assert!((pdf - pdf_li).abs() < 1e-6 * pdf, "{} {}", pdf, pdf_li);
+        }
+    }
+
+    #[test]
+    fn sphere_uv_wraps_around_the_y_axis() {
+        let cases = [
+            (Point3::new(1.0, 0.0, 0.0), 0.5, 0.5),
+            (Point3::new(0.0, 0.0, 1.0), 0.25, 0.5),
+            (Point3::new(0.0, 0.0, -1.0), 0.75, 0.5),
+            (Point3::new(0.0, 1.0, 0.0), 0.5, 1.0),
+            (Point3::new(0.0, -1.0, 0.0), 0.5, 0.0),
+        ];
+        for (p, u, v) in cases.iter() {
+            let (pu, pv) = Sphere::get_sphere_uv(p);
+            assert!((pu - u).abs() < 1e-12, "{} {}", pu, u);
+            assert!((pv - v).abs() < 1e-12, "{} {}", pv, v);
+        }
+    }
+
+    #[test]
+    fn sampled_pdf_matches_pdf_li() {
+        let light = DiffuseLight::new(Color::new(4.0, 4.0, 4.0));
+        let sphere = Sphere::new(Point3::new(0.0, 2.0, 0.0), 0.5, Arc::new(light));
+
+        // Cone sampling from outside, close by and far away, and area sampling inside
+        assert_sampled_pdf_matches(&sphere, &Point3::new(0.3, 0.0, 0.0));
+        assert_sampled_pdf_matches(&sphere, &Point3::new(0.0, -40.0, 10.0));
+        assert_sampled_pdf_matches(&sphere, &Point3::new(0.1, 2.2, 0.0));
+    }
+}
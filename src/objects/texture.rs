@@ -0,0 +1,108 @@
+use crate::utils::image::Image;
+use crate::utils::util::clamp;
+use crate::vec::vec3::{Color, Point3};
+use std::io;
+use std::sync::Arc;
+
+pub trait Texture: Sync + Send {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+
+    // Scalar textures (roughness, metallic, ...) are read from the first channel,
+    // so that packed maps can be split with a simple channel swizzle.
+    fn scalar(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        self.value(u, v, p).r()
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- SOLID COLOR -----
+// ----------------------------------------------------------------------
+pub struct SolidColor {
+    pub(crate) color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> SolidColor {
+        SolidColor { color }
+    }
+
+    pub fn scalar(value: f64) -> SolidColor {
+        SolidColor::new(Color::new(value, value, value))
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        self.color
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- CHECKER -----
+// ----------------------------------------------------------------------
+pub struct CheckerTexture {
+    pub(crate) odd: Arc<dyn Texture>,
+    pub(crate) even: Arc<dyn Texture>,
+    pub(crate) scale: f64,
+}
+
+impl CheckerTexture {
+    pub fn new(odd: Arc<dyn Texture>, even: Arc<dyn Texture>, scale: f64) -> CheckerTexture {
+        CheckerTexture { odd, even, scale }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let sines =
+            (self.scale * p.x()).sin() * (self.scale * p.y()).sin() * (self.scale * p.z()).sin();
+        match sines < 0.0 {
+            true => self.odd.value(u, v, p),
+            false => self.even.value(u, v, p),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- IMAGE -----
+// ----------------------------------------------------------------------
+// Bilinearly filtered image lookup, repeating outside of [0,1].
+pub struct ImageTexture {
+    pub(crate) image: Image,
+}
+
+impl ImageTexture {
+    pub fn new(image: Image) -> ImageTexture {
+        ImageTexture { image }
+    }
+
+    #[allow(dead_code)]
+    pub fn load_ppm(path: &str, srgb: bool) -> io::Result<ImageTexture> {
+        Ok(ImageTexture::new(Image::load_ppm(path, srgb)?))
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        let width = self.image.width;
+        let height = self.image.height;
+        if width == 0 || height == 0 {
+            return Color::new(0.0, 1.0, 1.0);
+        }
+
+        // Flip v to image coordinates, where row 0 is at the top
+        let x = clamp(u - u.floor(), 0.0, 1.0) * width as f64 - 0.5;
+        let y = clamp(1.0 - (v - v.floor()), 0.0, 1.0) * height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+
+        let wrap = |i: f64, n: usize| (i as i64).rem_euclid(n as i64) as usize;
+        let (x0, x1) = (wrap(x0, width), wrap(x0 + 1.0, width));
+        let (y0, y1) = (wrap(y0, height), wrap(y0 + 1.0, height));
+
+        (1.0 - dx) * (1.0 - dy) * self.image.pixel(x0, y0)
+            + dx * (1.0 - dy) * self.image.pixel(x1, y0)
+            + (1.0 - dx) * dy * self.image.pixel(x0, y1)
+            + dx * dy * self.image.pixel(x1, y1)
+    }
+}
@@ -0,0 +1,118 @@
+use crate::utils::complex::Complex;
+use crate::utils::util::clamp;
+use crate::vec::vec3::Color;
+use std::f64::consts::PI;
+
+// Wavelengths in nm averaged into the red, green and blue channels
+const WAVELENGTHS: [[f64; 4]; 3] = [
+    [595.0, 625.0, 655.0, 685.0],
+    [500.0, 520.0, 540.0, 560.0],
+    [410.0, 435.0, 460.0, 480.0],
+];
+
+// Thin transparent film on top of a surface, as on soap bubbles, oil slicks and
+// anodized metal. Light bouncing between the two film boundaries interferes,
+// which makes the reflectance wavelength dependent.
+#[derive(Clone, Copy)]
+pub struct ThinFilm {
+    // Film thickness in nanometers
+    pub(crate) thickness: f64,
+    pub(crate) reflection_index: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, reflection_index: f64) -> ThinFilm {
+        ThinFilm {
+            thickness,
+            reflection_index,
+        }
+    }
+
+    // Per channel reflectance for light arriving from a medium with IOR `outside`
+    // onto the film over a substrate with (complex) IOR `substrate`.
+    pub fn reflectance(&self, cos_theta_i: f64, outside: f64, substrate: &[Complex; 3]) -> Color {
+        let mut rgb = [0.0; 3];
+        for (channel, wavelengths) in WAVELENGTHS.iter().enumerate() {
+            let sum: f64 = wavelengths
+                .iter()
+                .map(|wavelength| {
+                    self.airy_reflectance(cos_theta_i, outside, substrate[channel], *wavelength)
+                })
+                .sum();
+            rgb[channel] = sum / wavelengths.len() as f64;
+        }
+        Color::new(rgb[0], rgb[1], rgb[2])
+    }
+
+    // Sums up all internal reflections of the film for a single wavelength (Airy formula)
+    fn airy_reflectance(&self, cos_theta_i: f64, n1: f64, n3: Complex, wavelength: f64) -> f64 {
+        let n1 = Complex::real(n1);
+        let n2 = Complex::real(self.reflection_index);
+        let cos1 = Complex::real(clamp(cos_theta_i, 0.0, 1.0));
+        let sin2_1 = Complex::real(1.0 - cos_theta_i * cos_theta_i);
+
+        // Snell's law into the film and the substrate; complex past total internal reflection
+        let cos2 = (Complex::real(1.0) - sin2_1 * (n1 * n1) / (n2 * n2)).sqrt();
+        let cos3 = (Complex::real(1.0) - sin2_1 * (n1 * n1) / (n3 * n3)).sqrt();
+
+        // Phase difference between consecutive internal bounces
+        let phase = (4.0 * PI * self.thickness / wavelength) * (n2 * cos2);
+        let shift = (Complex::new(0.0, 1.0) * phase).exp();
+
+        let airy = |r12: Complex, r23: Complex| {
+            let r = (r12 + r23 * shift) / (Complex::real(1.0) + r12 * r23 * shift);
+            r.norm()
+        };
+
+        let r12_s = fresnel_s(n1, cos1, n2, cos2);
+        let r23_s = fresnel_s(n2, cos2, n3, cos3);
+        let r12_p = fresnel_p(n1, cos1, n2, cos2);
+        let r23_p = fresnel_p(n2, cos2, n3, cos3);
+        let reflectance = (airy(r12_s, r23_s) + airy(r12_p, r23_p)) / 2.0;
+        clamp(reflectance, 0.0, 1.0)
+    }
+}
+
+// Fresnel amplitude coefficients for s and p polarized light
+fn fresnel_s(n_i: Complex, cos_i: Complex, n_t: Complex, cos_t: Complex) -> Complex {
+    (n_i * cos_i - n_t * cos_t) / (n_i * cos_i + n_t * cos_t)
+}
+
+fn fresnel_p(n_i: Complex, cos_i: Complex, n_t: Complex, cos_t: Complex) -> Complex {
+    (n_t * cos_i - n_i * cos_t) / (n_t * cos_i + n_i * cos_t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::microfacet::{fresnel_complex, fresnel_dielectric};
+
+    #[test]
+    fn vanishing_film_leaves_the_substrate_reflectance() {
+        let glass = [Complex::real(1.5); 3];
+        let gold = Complex::new(0.143, 3.983);
+        for &cos in &[1.0, 0.7, 0.3] {
+            // No thickness, or the same IOR as the surrounding medium
+            for film in &[ThinFilm::new(0.0, 1.33), ThinFilm::new(500.0, 1.0)] {
+                let r = film.reflectance(cos, 1.0, &glass);
+                assert!((r.g() - fresnel_dielectric(cos, 1.5)).abs() < 1e-9);
+                let r = film.reflectance(cos, 1.0, &[gold; 3]);
+                assert!((r.r() - fresnel_complex(cos, gold)).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn quarter_wave_film_cancels_the_reflection() {
+        // Index matched coating with an optical thickness of a quarter wavelength
+        let n = 1.5f64.sqrt();
+        let film = ThinFilm::new(540.0 / (4.0 * n), n);
+        let r = film.airy_reflectance(1.0, 1.0, Complex::real(1.5), 540.0);
+        assert!(r < 1e-9, "{}", r);
+
+        // Half a wavelength is an absentee layer
+        let film = ThinFilm::new(540.0 / (2.0 * n), n);
+        let r = film.airy_reflectance(1.0, 1.0, Complex::real(1.5), 540.0);
+        assert!((r - fresnel_dielectric(1.0, 1.5)).abs() < 1e-9, "{}", r);
+    }
+}
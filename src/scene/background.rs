@@ -0,0 +1,384 @@
+use crate::lights::light::SunLight;
+use crate::utils::distribution::Distribution2D;
+use crate::utils::image::Image;
+use crate::utils::util::{clamp, random_double};
+use crate::vec::vec3::{Color, Vec3};
+use std::f64::consts::PI;
+use std::io;
+
+// Direction sampled towards the background
+pub struct BackgroundSample {
+    // Unit direction from the shading point towards the background
+    pub(crate) wi: Vec3,
+    // Radiance arriving from `wi`, not divided by the density
+    pub(crate) radiance: Color,
+    // Solid angle density of having sampled `wi`
+    pub(crate) pdf: f64,
+}
+
+// Radiance arriving from infinitely far away, seen by every ray leaving the scene.
+pub trait Background: Sync + Send {
+    fn value(&self, dir: &Vec3) -> Color;
+
+    // Backgrounds without importance sampling are only found by rays escaping the scene
+    fn sample(&self) -> Option<BackgroundSample> {
+        None
+    }
+
+    fn pdf(&self, _dir: &Vec3) -> f64 {
+        0.0
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- GRADIENT -----
+// ----------------------------------------------------------------------
+// Blends linearly from the horizon color straight below to the zenith color straight above.
+pub struct Gradient {
+    pub(crate) horizon: Color,
+    pub(crate) zenith: Color,
+}
+
+impl Gradient {
+    pub fn new(horizon: Color, zenith: Color) -> Gradient {
+        Gradient { horizon, zenith }
+    }
+}
+
+impl Background for Gradient {
+    fn value(&self, dir: &Vec3) -> Color {
+        let t = 0.5 * (dir.unit().y() + 1.0);
+        (1.0 - t) * self.horizon + t * self.zenith
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- PREETHAM SKY -----
+// ----------------------------------------------------------------------
+// Analytic daylight model of Preetham et al. 1999, "A Practical Analytic Model for
+// Daylight". The sun disk itself is not part of the sky, add `sun_light` for it.
+pub struct PreethamSky {
+    // Unit direction towards the sun
+    pub(crate) sun_direction: Vec3,
+    // Haziness of the atmosphere, from 2 for a very clear sky to around 10 for haze
+    pub(crate) turbidity: f64,
+    // Scale from the model's kcd/m^2 to scene radiance
+    pub(crate) intensity: f64,
+    // Zenith values of luminance Y and chromaticity x, y
+    zenith: [f64; 3],
+    // Perez distribution coefficients A to E for Y, x and y
+    perez: [[f64; 5]; 3],
+}
+
+impl PreethamSky {
+    pub fn new(sun_direction: Vec3, turbidity: f64, intensity: f64) -> PreethamSky {
+        let sun_direction = sun_direction.unit();
+        let t = turbidity;
+        let theta_s = clamp(sun_direction.y(), -1.0, 1.0).acos().min(PI / 2.0);
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (theta2, theta3) = (theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x = t * t * (0.00166 * theta3 - 0.00375 * theta2 + 0.00209 * theta_s)
+            + t * (-0.02903 * theta3 + 0.06377 * theta2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * theta3 - 0.21196 * theta2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y = t * t * (0.00275 * theta3 - 0.00610 * theta2 + 0.00317 * theta_s)
+            + t * (-0.04214 * theta3 + 0.08970 * theta2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * theta3 - 0.26756 * theta2 + 0.06670 * theta_s + 0.26688);
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        // Normalize so the distributions pass through the zenith values straight up
+        let mut zenith = [zenith_luminance.max(0.0), zenith_x, zenith_y];
+        for (value, coefficients) in zenith.iter_mut().zip(perez.iter()) {
+            *value /= PreethamSky::perez(coefficients, 1.0, theta_s.cos());
+        }
+
+        PreethamSky {
+            sun_direction,
+            turbidity,
+            intensity,
+            zenith,
+            perez,
+        }
+    }
+
+    // Sun disk light matching this sky
+    pub fn sun_light(&self, irradiance: f64) -> SunLight {
+        SunLight::new(self.sun_direction, self.turbidity, irradiance)
+    }
+
+    // Perez et al. sky distribution for a view at cos(theta) from the zenith and
+    // cos(gamma) from the sun
+    fn perez(coefficients: &[f64; 5], cos_theta: f64, cos_gamma: f64) -> f64 {
+        let [a, b, c, d, e] = *coefficients;
+        let gamma = clamp(cos_gamma, -1.0, 1.0).acos();
+        (1.0 + a * (b / cos_theta).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+impl Background for PreethamSky {
+    fn value(&self, dir: &Vec3) -> Color {
+        // Below the horizon the sky keeps its horizon color, standing in for the ground
+        let dir = dir.unit();
+        let cos_theta = dir.y().max(0.01);
+        let cos_gamma = dir.dot(&self.sun_direction);
+        let [luminance, x, y] = [0, 1, 2]
+            .map(|i| self.zenith[i] * PreethamSky::perez(&self.perez[i], cos_theta, cos_gamma));
+        if y <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        // xyY to XYZ to linear sRGB
+        let big_x = x / y * luminance;
+        let big_z = (1.0 - x - y) / y * luminance;
+        let rgb = Color::new(
+            3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+            -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+            0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+        );
+        self.intensity * Color::new(rgb.r().max(0.0), rgb.g().max(0.0), rgb.b().max(0.0))
+    }
+}
+
+// ----------------------------------------------------------------------
+// ----- ENVIRONMENT MAP -----
+// ----------------------------------------------------------------------
+// Equirectangular HDR image around the scene, with +y up and the image center looking
+// down -z. Directions are importance sampled proportional to the map's luminance.
+pub struct EnvironmentMap {
+    pub(crate) image: Image,
+    pub(crate) intensity: f64,
+    // Rotation about the y axis, in radians
+    pub(crate) rotation: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    // `rotation` turns the map about the vertical axis, in degrees
+    pub fn new(image: Image, intensity: f64, rotation: f64) -> EnvironmentMap {
+        // Rows near the poles cover less solid angle, so weight them by sin(theta)
+        let weights: Vec<f64> = image
+            .pixels
+            .iter()
+            .enumerate()
+            .map(|(i, pixel)| {
+                let row = i / image.width;
+                let sin_theta = (PI * (row as f64 + 0.5) / image.height as f64).sin();
+                pixel.luminance().max(0.0) * sin_theta
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, image.width, image.height);
+        EnvironmentMap {
+            image,
+            intensity,
+            rotation: rotation.to_radians(),
+            distribution,
+        }
+    }
+
+    // Loads a Radiance .hdr or OpenEXR file, picked by its extension
+    pub fn load(path: &str, intensity: f64, rotation: f64) -> io::Result<EnvironmentMap> {
+        let image = match path.to_lowercase() {
+            p if p.ends_with(".hdr") => Image::load_hdr(path)?,
+            p if p.ends_with(".exr") => Image::load_exr(path)?,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Environment maps must be .hdr or .exr files",
+                ))
+            }
+        };
+        Ok(EnvironmentMap::new(image, intensity, rotation))
+    }
+
+    fn rotate(dir: &Vec3, angle: f64) -> Vec3 {
+        let (sin, cos) = angle.sin_cos();
+        Vec3::new(
+            cos * dir.x() + sin * dir.z(),
+            dir.y(),
+            -sin * dir.x() + cos * dir.z(),
+        )
+    }
+
+    // Maps a world direction to (u, v) in [0,1), v = 0 at the top row
+    fn direction_to_uv(&self, dir: &Vec3) -> (f64, f64) {
+        let d = EnvironmentMap::rotate(&dir.unit(), -self.rotation);
+        let u = 0.5 + d.x().atan2(-d.z()) / (2.0 * PI);
+        let v = clamp(d.y(), -1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let (sin_theta, cos_theta) = (PI * v).sin_cos();
+        let (sin_phi, cos_phi) = (2.0 * PI * (u - 0.5)).sin_cos();
+        let d = Vec3::new(sin_theta * sin_phi, cos_theta, -sin_theta * cos_phi);
+        EnvironmentMap::rotate(&d, self.rotation)
+    }
+
+    fn lookup(&self, u: f64, v: f64) -> Color {
+        let x = (u * self.image.width as f64) as usize;
+        let y = (v * self.image.height as f64) as usize;
+        self.intensity * self.image.pixel(x, y)
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn value(&self, dir: &Vec3) -> Color {
+        let (u, v) = self.direction_to_uv(dir);
+        self.lookup(u, v)
+    }
+
+    fn sample(&self) -> Option<BackgroundSample> {
+        let (u, v, pdf_uv) = self
+            .distribution
+            .sample_continuous(random_double(), random_double());
+        let sin_theta = (PI * v).sin();
+        if pdf_uv <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        Some(BackgroundSample {
+            wi: self.uv_to_direction(u, v),
+            radiance: self.lookup(u, v),
+            // Change of variables from the unit square to the sphere
+            pdf: pdf_uv / (2.0 * PI * PI * sin_theta),
+        })
+    }
+
+    fn pdf(&self, dir: &Vec3) -> f64 {
+        let (u, v) = self.direction_to_uv(dir);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lights::light::Light;
+
+    // Equirectangular map with a bright band around the horizon
+    fn environment_map(rotation: f64) -> EnvironmentMap {
+        let (width, height) = (16, 8);
+        let pixels = (0..width * height)
+            .map(|i| match i / width {
+                3 | 4 => Color::new(4.0, 4.0, 4.0),
+                _ => Color::new(0.5, 0.5, 0.5),
+            })
+            .collect();
+        let image = Image {
+            width,
+            height,
+            pixels,
+        };
+        EnvironmentMap::new(image, 1.0, rotation)
+    }
+
+    #[test]
+    fn directions_round_trip_through_uv() {
+        for &rotation in &[0.0, 90.0, -33.0] {
+            let map = environment_map(rotation);
+            for &(u, v) in &[(0.1, 0.2), (0.5, 0.5), (0.75, 0.9), (0.999, 0.01)] {
+                let (u2, v2) = map.direction_to_uv(&map.uv_to_direction(u, v));
+                assert!(
+                    (u - u2).abs() < 1e-9 && (v - v2).abs() < 1e-9,
+                    "{} {}",
+                    u,
+                    v
+                );
+            }
+        }
+
+        // The center of the unrotated map looks down -z, its top row straight up
+        let map = environment_map(0.0);
+        assert!((map.uv_to_direction(0.5, 0.5) - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-12);
+        assert!((map.uv_to_direction(0.3, 0.0) - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn sampled_directions_report_their_pdf() {
+        let map = environment_map(20.0);
+        for _ in 0..1000 {
+            let sample = map.sample().unwrap();
+            let pdf = map.pdf(&sample.wi);
+            assert!(
+                (sample.pdf - pdf).abs() < 1e-9 * pdf,
+                "{} {}",
+                sample.pdf,
+                pdf
+            );
+            let radiance = map.value(&sample.wi);
+            assert!((sample.radiance - radiance).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_one_over_the_sphere() {
+        let map = environment_map(0.0);
+        let (n_theta, n_phi) = (400, 400);
+        let mut total = 0.0;
+        for i in 0..n_theta {
+            let theta = PI * (i as f64 + 0.5) / n_theta as f64;
+            for j in 0..n_phi {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n_phi as f64;
+                let dir = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                total +=
+                    map.pdf(&dir) * theta.sin() * (PI / n_theta as f64) * (2.0 * PI / n_phi as f64);
+            }
+        }
+        assert!((total - 1.0).abs() < 1e-3, "{}", total);
+    }
+
+    #[test]
+    fn sky_brightens_towards_the_sun() {
+        let sun = Vec3::new(1.0, 0.5, 0.0).unit();
+        let sky = PreethamSky::new(sun, 3.0, 1.0);
+        let near_sun = sky.value(&Vec3::new(1.0, 0.6, 0.1)).luminance();
+        let away = sky.value(&Vec3::new(-1.0, 0.6, 0.0)).luminance();
+        let zenith = sky.value(&Vec3::new(0.0, 1.0, 0.0));
+        assert!(near_sun > away && away > 0.0);
+        assert!(zenith.r() > 0.0 && zenith.b() > zenith.r());
+    }
+
+    #[test]
+    fn sun_light_shines_from_the_sky_sun_direction() {
+        let sky = PreethamSky::new(Vec3::new(0.3, 0.8, -0.2), 2.5, 1.0);
+        let sun = sky.sun_light(1000.0);
+        for _ in 0..100 {
+            let sample = sun.sample_li(&Vec3::new(0.0, 0.0, 0.0)).unwrap();
+            assert!(sample.wi.dot(&sky.sun_direction) >= sun.cos_max - 1e-12);
+            assert!(sun.le(&sample.wi).luminance() > 0.0);
+        }
+        assert_eq!(sun.le(&-sky.sun_direction).luminance(), 0.0);
+    }
+}
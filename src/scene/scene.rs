@@ -1,22 +1,58 @@
-use crate::camera::camera::Camera;
-use crate::objects::hittable::{Hittable, HittableList};
-use crate::utils::util::{random_double, write_color, write_pixels_to_file, random_double_in_range};
+use crate::camera::camera::{
+    Camera, EquirectangularCamera, ExposureSettings, PerspectiveCamera, PhysicalCamera,
+    StereoCamera, StereoLayout,
+};
+use crate::camera::aperture::Aperture;
+use crate::camera::realistic_camera::{LensElement, RealisticCamera};
+use crate::lights::light::{DirectionalLight, Light, PointLight, SpotLight};
+use crate::lights::light_bvh::LightBvh;
+use crate::objects::hittable::{HitRecord, Hittable, HittableList};
+use crate::scene::background::{Background, EnvironmentMap, Gradient, PreethamSky};
+use crate::utils::util::{
+    power_heuristic, random_double, random_double_in_range, write_color, write_pixels_to_file,
+};
 use crate::vec::vec3::{Color, Point3, Ray, Vec3};
 use std::f64::INFINITY;
+use std::io;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use std::cmp;
-use crate::objects::material::{Lambertian, Metal, Dielectric};
+use crate::objects::material::{
+    AlphaMask, Coated, Conductor, Dielectric, DiffuseLight, Lambertian, Material, Metal,
+    MixMaterial, OrenNayar, Principled, RoughDielectric, Sheen, Subsurface,
+};
+use crate::objects::medium::{Medium, MediumEvent};
+use crate::objects::texture::{CheckerTexture, SolidColor};
+use crate::objects::thin_film::ThinFilm;
+use crate::objects::quad::Quad;
 use crate::objects::sphere::Sphere;
 use rayon::slice::ParallelSliceMut;
 use rayon::iter::{IndexedParallelIterator, ParallelIterator};
 
 const SAMPLES_PER_PIXEL: u32 = 500;
 const MAX_DEPTH: u32 = 50;
+// Scattering events a random walk through a medium may take before it is dropped.
+// Kept apart from MAX_DEPTH, dense media scatter far more often than rays bounce.
+const MAX_MEDIUM_SCATTERS: u32 = 256;
+// Ignore hits very near zero to avoid shadow acne and rays re-hitting the surface they
+// just left, which random walks inside media would otherwise do all the time.
+const T_MIN: f64 = 0.001;
+
+// BSDF sample a ray was scattered with, to weigh the light it finds against light sampling
+#[derive(Clone, Copy)]
+struct BsdfSample {
+    pdf: f64,
+    // Normal of the surface the ray left
+    normal: Vec3,
+}
 
 pub struct Scene {
     world: HittableList,
-    camera: Camera,
+    lights: Vec<Arc<dyn Light>>,
+    // Built over all lights once the scene is set up, when rendering starts
+    light_bvh: LightBvh,
+    background: Arc<dyn Background>,
+    camera: Arc<dyn Camera>,
     image_width: u32,
     image_height: u32,
     aspect_ratio: f64,
@@ -27,7 +63,7 @@ pub struct Scene {
 impl Scene {
     pub fn new(
         world: HittableList,
-        camera: Camera,
+        camera: Arc<dyn Camera>,
         image_width: u32,
         aspect_ratio: f64,
     ) -> Scene {
@@ -39,6 +75,12 @@ impl Scene {
         let chunk_size = (image_height / 10) * image_width;
         Scene {
             world,
+            lights: Vec::new(),
+            light_bvh: LightBvh::new(&[]),
+            background: Arc::new(Gradient::new(
+                Color::new(1.0, 1.0, 1.0),
+                Color::new(0.5, 0.7, 1.0),
+            )),
             camera,
             image_width,
             image_height,
@@ -48,7 +90,36 @@ impl Scene {
         }
     }
 
-    pub fn multithreadet_rendering(&self) {
+    pub fn add_light(&mut self, light: Arc<dyn Light>) {
+        self.lights.push(light);
+    }
+
+    // Adds an emissive sphere to the world that is also sampled as an area light. Light
+    // samples are taken on the sphere where it is when the shutter opens, so it must
+    // not move.
+    pub fn add_sphere_light(&mut self, mut sphere: Sphere) {
+        assert!(
+            sphere.velocity.length_squared() == 0.0,
+            "Moving spheres can't be sampled as area lights"
+        );
+        sphere.light = Some(self.lights.len());
+        self.world.add(Box::new(sphere.clone()));
+        self.add_light(Arc::new(sphere));
+    }
+
+    // Adds an emissive quad to the world that is also sampled as an area light
+    pub fn add_quad_light(&mut self, mut quad: Quad) {
+        quad.light = Some(self.lights.len());
+        self.world.add(Box::new(quad.clone()));
+        self.add_light(Arc::new(quad));
+    }
+
+    pub fn set_background(&mut self, background: Arc<dyn Background>) {
+        self.background = background;
+    }
+
+    pub fn multithreadet_rendering(&mut self) {
+        self.light_bvh = LightBvh::new(&self.lights);
         let now = Instant::now();
         self.pixel_vec
             .lock()
@@ -92,36 +163,191 @@ impl Scene {
             let u =
                 (f64::from(image_width_pos) + random_double()) / f64::from(self.image_width - 1);
             let v = (f64::from(line_nbr) + random_double()) / f64::from(self.image_height - 1);
-            let r = self.camera.get_ray(u, v);
-            pixel_color = pixel_color + self.ray_color(r, MAX_DEPTH)
+            if let Some((r, weight)) = self.camera.sample_ray(u, v) {
+                pixel_color = pixel_color + weight * self.ray_color(r, MAX_DEPTH, None, None)
+            }
         }
         write_color(pixel_color, SAMPLES_PER_PIXEL as u16)
     }
 
-    fn ray_color(&self, ray: Ray, depth: u32) -> Color {
+    // `medium` is the participating medium the ray currently travels through, if any.
+    // `bsdf_sample` is how the previous surface sampled this ray, or None if the ray
+    // could not have been found by light sampling (camera rays, specular bounces and
+    // medium scattering).
+    fn ray_color(
+        &self,
+        ray: Ray,
+        depth: u32,
+        medium: Option<&Medium>,
+        bsdf_sample: Option<BsdfSample>,
+    ) -> Color {
         // If we've exceeded the ray bounce limit, no more light is gathered
         if depth <= 0 {
             return Color::new(0.0, 0.0, 0.0);
         }
 
-        match self.world.hit(ray, 0.0, INFINITY) {
+        // Inside a medium the ray random walks until it reaches a surface
+        let mut ray = ray;
+        let mut bsdf_sample = bsdf_sample;
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut scatters = 0;
+        let hit = loop {
+            let hit = self.world.hit(ray, T_MIN, INFINITY);
+            let (medium, hit_record) = match (medium, &hit) {
+                (Some(medium), Some(hit_record)) => (medium, hit_record),
+                _ => break hit,
+            };
+            let ray_length = ray.dir.length();
+            match medium.sample(hit_record.t * ray_length) {
+                MediumEvent::Scatter { distance, weight } => {
+                    scatters += 1;
+                    if scatters > MAX_MEDIUM_SCATTERS {
+                        return Color::new(0.0, 0.0, 0.0);
+                    }
+                    throughput = throughput * weight;
+                    ray = Ray::new_at(
+                        ray.at(distance / ray_length),
+                        medium.sample_phase(&ray.dir.unit()),
+                        ray.time,
+                    );
+                    bsdf_sample = None;
+                }
+                MediumEvent::Surface { weight } => {
+                    throughput = throughput * weight;
+                    break hit;
+                }
+            }
+        };
+
+        match hit {
             Some(hit_record) => {
-                if let Some((scattered, attenuation)) =
-                    hit_record.material.scatter(&ray, &hit_record)
-                {
-                    attenuation * self.ray_color(scattered, depth - 1)
+                // Area lights are also sampled explicitly, so share their emission with
+                // the light samples taken at the last surface
+                let mut emitted = hit_record.material.emitted(&hit_record);
+                if let (Some(light), Some(bsdf_sample)) = (hit_record.light, bsdf_sample) {
+                    let light_pdf = self.light_bvh.pmf(&ray.origin, &bsdf_sample.normal, light)
+                        * self.lights[light].pdf_li(&ray.origin, &ray.dir);
+                    emitted = power_heuristic(bsdf_sample.pdf, light_pdf) * emitted;
+                }
+                let emitted = emitted
+                    + self.direct_lighting(&ray, &hit_record)
+                    + self.background_lighting(&ray, &hit_record);
+                if let Some(record) = hit_record.material.scatter(&ray, &hit_record) {
+                    let scattered = record.scattered;
+                    let next_sample = match record.is_specular {
+                        true => None,
+                        false => Some(BsdfSample {
+                            pdf: hit_record
+                                .material
+                                .scattering_pdf(&ray, &hit_record, &scattered),
+                            normal: hit_record.normal,
+                        }),
+                    };
+
+                    // Transmission through a front face enters the interior of the object,
+                    // through a back face it leaves it again.
+                    let transmitted = scattered.dir.dot(&hit_record.normal) < 0.0;
+                    let next_medium = match (transmitted, hit_record.front_face) {
+                        (true, true) => hit_record.material.interior_medium(&hit_record),
+                        (true, false) => None,
+                        (false, _) => medium,
+                    };
+                    throughput
+                        * (emitted
+                            + record.attenuation
+                                * self.ray_color(scattered, depth - 1, next_medium, next_sample))
                 } else {
-                    Color::new(0.0, 0.0, 0.0)
+                    throughput * emitted
                 }
             }
             None => {
-                let unit_direction = ray.dir.unit();
-                let t = 0.5 * (unit_direction.y() + 1.0);
-                (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+                // Share the background with its explicit samples taken at the last surface
+                let weight = match bsdf_sample {
+                    Some(bsdf_sample) => match self.background.pdf(&ray.dir) {
+                        light_pdf if light_pdf > 0.0 => power_heuristic(bsdf_sample.pdf, light_pdf),
+                        _ => 1.0,
+                    },
+                    None => 1.0,
+                };
+                // Visible light sources at infinity, such as the sun disk, are found by
+                // light sampling everywhere else
+                let visible_lights = match bsdf_sample {
+                    Some(_) => Color::new(0.0, 0.0, 0.0),
+                    None => self
+                        .lights
+                        .iter()
+                        .fold(Color::new(0.0, 0.0, 0.0), |sum, light| sum + light.le(&ray.dir)),
+                };
+                throughput * (weight * self.background.value(&ray.dir) + visible_lights)
             }
         }
     }
 
+    // Light arriving straight from the scene's lights. Lights at infinity are all
+    // sampled, of the others a single one is picked by its estimated contribution.
+    fn direct_lighting(&self, ray: &Ray, hit_record: &HitRecord) -> Color {
+        let mut direct = Color::new(0.0, 0.0, 0.0);
+        for light in self.light_bvh.unbounded() {
+            direct = direct + self.sample_light(*light, 1.0, ray, hit_record);
+        }
+        if let Some((light, pmf)) = self.light_bvh.sample(&hit_record.p, &hit_record.normal) {
+            direct = direct + self.sample_light(light, pmf, ray, hit_record);
+        }
+        direct
+    }
+
+    // Connects the hit point to a sample on one light with a shadow ray. `pmf` is the
+    // probability the light was picked with.
+    fn sample_light(&self, light: usize, pmf: f64, ray: &Ray, hit_record: &HitRecord) -> Color {
+        let sample = match self.lights[light].sample_li(&hit_record.p) {
+            Some(sample) => sample,
+            None => return Color::new(0.0, 0.0, 0.0),
+        };
+        let shadow_ray = Ray::new_at(hit_record.p, sample.wi, ray.time);
+        let bsdf = hit_record.material.eval_bsdf(ray, hit_record, &shadow_ray);
+        if bsdf.e.iter().all(|x| *x == 0.0)
+            || self
+                .world
+                .hit(shadow_ray, T_MIN, sample.distance * (1.0 - 1e-6))
+                .is_some()
+        {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        // Lights that rays can hit by chance are shared with BSDF sampling
+        let weight = match sample.pdf {
+            Some(light_pdf) => {
+                let bsdf_pdf = hit_record
+                    .material
+                    .scattering_pdf(ray, hit_record, &shadow_ray);
+                power_heuristic(pmf * light_pdf, bsdf_pdf)
+            }
+            None => 1.0,
+        };
+        (weight / pmf) * (bsdf * sample.radiance)
+    }
+
+    // Light from the background, importance sampled and combined with BSDF sampling
+    // through multiple importance sampling
+    fn background_lighting(&self, ray: &Ray, hit_record: &HitRecord) -> Color {
+        let sample = match self.background.sample() {
+            Some(sample) => sample,
+            None => return Color::new(0.0, 0.0, 0.0),
+        };
+        let shadow_ray = Ray::new_at(hit_record.p, sample.wi, ray.time);
+        let bsdf = hit_record.material.eval_bsdf(ray, hit_record, &shadow_ray);
+        if bsdf.e.iter().all(|x| *x == 0.0)
+            || self.world.hit(shadow_ray, T_MIN, f64::INFINITY).is_some()
+        {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let bsdf_pdf = hit_record
+            .material
+            .scattering_pdf(ray, hit_record, &shadow_ray);
+        let weight = power_heuristic(sample.pdf, bsdf_pdf) / sample.pdf;
+        weight * (bsdf * sample.radiance)
+    }
+
     fn chunk_idx_to_start_pixel_pos(&self, chunk_idx: usize) -> usize {
         self.chunk_size as usize * chunk_idx
     }
@@ -225,7 +451,7 @@ impl Scene {
         let aspect_ratio = 3.0 / 2.0;
         let image_width: u32 = 1200;
 
-        let camera = Camera::new(
+        let camera = PerspectiveCamera::new(
             lookfrom,
             lookat,
             vup,
@@ -235,7 +461,7 @@ impl Scene {
             dist_to_focus,
         );
 
-        Scene::new(world, camera, image_width, aspect_ratio)
+        Scene::new(world, Arc::new(camera), image_width, aspect_ratio)
     }
 
     #[allow(dead_code)]
@@ -280,7 +506,7 @@ impl Scene {
         let aspect_ratio = 16.0 / 9.0;
         let image_width: u32 = 400;
 
-        let camera = Camera::new(
+        let camera = PerspectiveCamera::new(
             lookfrom,
             lookat,
             vup,
@@ -290,6 +516,768 @@ impl Scene {
             dist_to_focus,
         );
 
-        Scene::new(world, camera, image_width, aspect_ratio)
+        Scene::new(world, Arc::new(camera), image_width, aspect_ratio)
     }
-}
\ No newline at end of file
+
+    #[allow(dead_code)]
+    pub(crate) fn setup_materials_scene() -> Scene {
+        let mut world = HittableList::default();
+
+        let material_ground = OrenNayar::new(Color::new(0.5, 0.5, 0.5), 30.0);
+        let material_gold = Conductor::gold(0.3);
+        let material_soap_bubble = Dielectric::new(1.0).with_thin_film(ThinFilm::new(400.0, 1.33));
+        let material_anodized = Conductor::aluminium(0.1).with_thin_film(ThinFilm::new(300.0, 1.6));
+        let material_wax = Subsurface::new(
+            Color::new(0.9, 0.75, 0.55),
+            Color::new(0.4, 0.2, 0.1),
+            1.4,
+            0.0,
+        );
+        let material_frosted = RoughDielectric::new(1.5, 0.2);
+        let material_colored_glass =
+            Dielectric::with_absorption(1.5, Color::new(0.1, 0.8, 1.2));
+        let material_varnished = Coated::new(
+            Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1))),
+            1.5,
+            0.0,
+        );
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Arc::new(material_ground),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(-2.2, 1.0, 0.0),
+            1.0,
+            Arc::new(material_gold),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, 1.0, 0.0),
+            1.0,
+            Arc::new(material_frosted),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(2.2, 1.0, 0.0),
+            1.0,
+            Arc::new(material_colored_glass),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(-0.5, 0.4, 2.0),
+            0.4,
+            Arc::new(material_varnished),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(1.2, 0.6, 2.2),
+            0.6,
+            Arc::new(material_soap_bubble),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(-2.0, 0.3, 2.5),
+            0.3,
+            Arc::new(material_anodized),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(3.0, 0.5, 2.0),
+            0.5,
+            Arc::new(material_wax),
+        )));
+
+        let lookfrom = Point3::new(0.0, 3.0, 10.0);
+        let lookat = Point3::new(0.0, 1.0, 0.0);
+        let vup = Vec3::new(0.0, 1.0, 0.0);
+        let dist_to_focus = (lookfrom - lookat).length();
+        let aperture = 0.0;
+
+        let aspect_ratio = 16.0 / 9.0;
+        let image_width: u32 = 400;
+
+        let camera = PerspectiveCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            30.0,
+            aspect_ratio,
+            aperture,
+            dist_to_focus,
+        );
+
+        Scene::new(world, Arc::new(camera), image_width, aspect_ratio)
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn setup_principled_scene() -> Scene {
+        let mut world = HittableList::default();
+
+        let checker = CheckerTexture::new(
+            Arc::new(SolidColor::new(Color::new(0.2, 0.3, 0.1))),
+            Arc::new(SolidColor::new(Color::new(0.9, 0.9, 0.9))),
+            10.0,
+        );
+        let material_ground = Principled::new(Arc::new(checker));
+        let material_plastic = Principled::new(Arc::new(SolidColor::new(Color::new(
+            0.8, 0.1, 0.1,
+        ))))
+        .with_roughness(Arc::new(SolidColor::scalar(0.6)))
+        .with_clearcoat(
+            Arc::new(SolidColor::scalar(1.0)),
+            Arc::new(SolidColor::scalar(0.05)),
+        );
+        let material_brushed = Principled::new(Arc::new(SolidColor::new(Color::new(
+            0.9, 0.9, 0.9,
+        ))))
+        .with_metallic(Arc::new(SolidColor::scalar(1.0)))
+        .with_roughness(Arc::new(SolidColor::scalar(0.35)));
+        let rust_mask = CheckerTexture::new(
+            Arc::new(SolidColor::scalar(0.0)),
+            Arc::new(SolidColor::scalar(1.0)),
+            6.0,
+        );
+        let material_rusty = MixMaterial::with_mask(
+            Arc::new(Conductor::copper(0.2)),
+            Arc::new(OrenNayar::new(Color::new(0.45, 0.15, 0.05), 40.0)),
+            Arc::new(rust_mask),
+        );
+        let cage_opacity = CheckerTexture::new(
+            Arc::new(SolidColor::scalar(0.0)),
+            Arc::new(SolidColor::scalar(1.0)),
+            8.0,
+        );
+        let material_cage = AlphaMask::new(
+            Arc::new(Lambertian::new(Color::new(0.2, 0.4, 0.8))),
+            Arc::new(cage_opacity),
+            0.5,
+        );
+        let material_velvet = Sheen::new(
+            Arc::new(OrenNayar::new(Color::new(0.25, 0.02, 0.1), 20.0)),
+            Color::new(0.9, 0.5, 0.7),
+            0.4,
+        );
+        let material_lamp = Principled::new(Arc::new(SolidColor::scalar(0.0)))
+            .with_emission(Arc::new(SolidColor::new(Color::new(4.0, 3.0, 2.0))));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Arc::new(material_ground),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(-1.1, 1.0, 0.0),
+            1.0,
+            Arc::new(material_plastic),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(1.1, 1.0, 0.0),
+            1.0,
+            Arc::new(material_brushed),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, 0.3, 1.5),
+            0.3,
+            Arc::new(material_lamp),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(-2.9, 0.7, 0.5),
+            0.7,
+            Arc::new(material_rusty),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(2.9, 0.7, 0.5),
+            0.7,
+            Arc::new(material_cage),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(-1.6, 0.45, 2.2),
+            0.45,
+            Arc::new(material_velvet),
+        )));
+
+        let lookfrom = Point3::new(0.0, 3.0, 10.0);
+        let lookat = Point3::new(0.0, 1.0, 0.0);
+        let vup = Vec3::new(0.0, 1.0, 0.0);
+        let dist_to_focus = (lookfrom - lookat).length();
+        let aperture = 0.0;
+
+        let aspect_ratio = 16.0 / 9.0;
+        let image_width: u32 = 400;
+
+        let camera = PerspectiveCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            30.0,
+            aspect_ratio,
+            aperture,
+            dist_to_focus,
+        );
+
+        Scene::new(world, Arc::new(camera), image_width, aspect_ratio)
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn setup_lights_scene() -> Scene {
+        let mut world = HittableList::default();
+
+        let material_ground = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let material_clay = OrenNayar::new(Color::new(0.8, 0.5, 0.3), 20.0);
+        let material_copper = Conductor::copper(0.25);
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Arc::new(material_ground),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(-1.1, 1.0, 0.0),
+            1.0,
+            Arc::new(material_clay),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(1.1, 1.0, 0.0),
+            1.0,
+            Arc::new(material_copper),
+        )));
+
+        let lookfrom = Point3::new(0.0, 3.0, 10.0);
+        let lookat = Point3::new(0.0, 1.0, 0.0);
+        let vup = Vec3::new(0.0, 1.0, 0.0);
+        let dist_to_focus = (lookfrom - lookat).length();
+        let aperture = 0.0;
+
+        let aspect_ratio = 16.0 / 9.0;
+        let image_width: u32 = 400;
+
+        let camera = PerspectiveCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            30.0,
+            aspect_ratio,
+            aperture,
+            dist_to_focus,
+        );
+
+        let mut scene = Scene::new(world, Arc::new(camera), image_width, aspect_ratio);
+        scene.add_light(Arc::new(PointLight::new(
+            Point3::new(-3.0, 4.0, 3.0),
+            Color::new(20.0, 18.0, 15.0),
+        )));
+        scene.add_light(Arc::new(SpotLight::new(
+            Point3::new(3.0, 5.0, 2.0),
+            Point3::new(1.1, 0.0, 0.0),
+            Color::new(10.0, 30.0, 60.0),
+            15.0,
+            25.0,
+        )));
+        scene.add_light(Arc::new(DirectionalLight::new(
+            Vec3::new(1.0, -1.0, -0.5),
+            Color::new(1.0, 0.9, 0.7),
+        )));
+        scene
+    }
+
+    // Product shot lit only by an HDR environment map (.hdr or .exr)
+    #[allow(dead_code)]
+    pub(crate) fn setup_environment_scene(path: &str) -> io::Result<Scene> {
+        let mut world = HittableList::default();
+
+        let material_ground = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let material_plastic = Principled::new(Arc::new(SolidColor::new(Color::new(
+            0.8, 0.1, 0.1,
+        ))))
+        .with_roughness(Arc::new(SolidColor::scalar(0.2)));
+        let material_gold = Conductor::gold(0.1);
+        let material_frosted = RoughDielectric::new(1.5, 0.3);
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Arc::new(material_ground),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(-2.2, 1.0, 0.0),
+            1.0,
+            Arc::new(material_plastic),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, 1.0, 0.0),
+            1.0,
+            Arc::new(material_gold),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(2.2, 1.0, 0.0),
+            1.0,
+            Arc::new(material_frosted),
+        )));
+
+        let lookfrom = Point3::new(0.0, 3.0, 12.0);
+        let lookat = Point3::new(0.0, 1.0, 0.0);
+        let vup = Vec3::new(0.0, 1.0, 0.0);
+        let dist_to_focus = (lookfrom - lookat).length();
+        let aperture = 0.0;
+
+        let aspect_ratio = 16.0 / 9.0;
+        let image_width: u32 = 400;
+
+        let camera = PerspectiveCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            30.0,
+            aspect_ratio,
+            aperture,
+            dist_to_focus,
+        );
+
+        let mut scene = Scene::new(world, Arc::new(camera), image_width, aspect_ratio);
+        scene.set_background(Arc::new(EnvironmentMap::load(path, 1.0, 0.0)?));
+        Ok(scene)
+    }
+
+    // Time of day lighting from an analytic sky and its sun, low in the afternoon
+    #[allow(dead_code)]
+    pub(crate) fn setup_sky_scene() -> Scene {
+        let mut world = HittableList::default();
+
+        let material_ground = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let material_clay = OrenNayar::new(Color::new(0.8, 0.5, 0.3), 20.0);
+        let material_silver = Conductor::silver(0.05);
+        let material_glass = Dielectric::new(1.5);
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Arc::new(material_ground),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(-2.2, 1.0, 0.0),
+            1.0,
+            Arc::new(material_clay),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, 1.0, 0.0),
+            1.0,
+            Arc::new(material_silver),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(2.2, 1.0, 0.0),
+            1.0,
+            Arc::new(material_glass),
+        )));
+
+        let lookfrom = Point3::new(0.0, 3.0, 12.0);
+        let lookat = Point3::new(0.0, 1.0, 0.0);
+        let vup = Vec3::new(0.0, 1.0, 0.0);
+        let dist_to_focus = (lookfrom - lookat).length();
+        let aperture = 0.0;
+
+        let aspect_ratio = 16.0 / 9.0;
+        let image_width: u32 = 400;
+
+        let camera = PerspectiveCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            30.0,
+            aspect_ratio,
+            aperture,
+            dist_to_focus,
+        );
+
+        let mut scene = Scene::new(world, Arc::new(camera), image_width, aspect_ratio);
+        let sky = PreethamSky::new(Vec3::new(-1.0, 0.45, -0.6), 3.0, 0.1);
+        scene.add_light(Arc::new(sky.sun_light(3.0)));
+        scene.set_background(Arc::new(sky));
+        scene
+    }
+
+    // 360 degree panorama from the middle of a ring of spheres under the daylight sky,
+    // for VR viewers or as an environment map. With a stereo layout, renders both eyes
+    // of an omnidirectional stereo panorama.
+    #[allow(dead_code)]
+    pub(crate) fn setup_panorama_scene(stereo: Option<StereoLayout>) -> Scene {
+        let mut world = HittableList::default();
+
+        let material_ground = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Arc::new(material_ground),
+        )));
+
+        let materials: [Arc<dyn Material>; 4] = [
+            Arc::new(OrenNayar::new(Color::new(0.8, 0.5, 0.3), 20.0)),
+            Arc::new(Conductor::silver(0.05)),
+            Arc::new(Dielectric::new(1.5)),
+            Arc::new(Conductor::copper(0.25)),
+        ];
+        for i in 0..8 {
+            let angle = f64::from(i) * std::f64::consts::PI / 4.0;
+            world.add(Box::new(Sphere::new(
+                Point3::new(4.0 * angle.sin(), 1.0, -4.0 * angle.cos()),
+                1.0,
+                Arc::clone(&materials[i as usize % materials.len()]),
+            )));
+        }
+
+        let lookfrom = Point3::new(0.0, 1.0, 0.0);
+        let lookat = Point3::new(0.0, 1.0, -1.0);
+        let vup = Vec3::new(0.0, 1.0, 0.0);
+
+        let image_width: u32 = 800;
+        // Average distance between human pupils
+        let interpupillary = 0.064;
+
+        let (camera, aspect_ratio): (Arc<dyn Camera>, f64) = match stereo {
+            Some(layout) => (
+                Arc::new(StereoCamera::ods(lookfrom, lookat, vup, interpupillary, layout)),
+                layout.image_aspect_ratio(2.0),
+            ),
+            None => (Arc::new(EquirectangularCamera::new(lookfrom, lookat, vup)), 2.0),
+        };
+
+        let mut scene = Scene::new(world, camera, image_width, aspect_ratio);
+        let sky = PreethamSky::new(Vec3::new(-1.0, 0.45, -0.6), 3.0, 0.1);
+        scene.add_light(Arc::new(sky.sun_light(3.0)));
+        scene.set_background(Arc::new(sky));
+        scene
+    }
+
+    // Row of spheres at dusk with a grid of small lamps far behind, photographed through
+    // a 50 mm double Gauss lens with a six bladed aperture, focused on the first sphere.
+    // The out of focus lamps show the hexagonal bokeh of the lens and the corners its
+    // vignetting.
+    #[allow(dead_code)]
+    pub(crate) fn setup_lens_scene() -> Scene {
+        let mut world = HittableList::default();
+
+        let checker = CheckerTexture::new(
+            Arc::new(SolidColor::new(Color::new(0.2, 0.3, 0.1))),
+            Arc::new(SolidColor::new(Color::new(0.9, 0.9, 0.9))),
+            2.0,
+        );
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Arc::new(Principled::new(Arc::new(checker))),
+        )));
+
+        let materials: [Arc<dyn Material>; 3] = [
+            Arc::new(OrenNayar::new(Color::new(0.8, 0.5, 0.3), 20.0)),
+            Arc::new(Conductor::gold(0.1)),
+            Arc::new(Lambertian::new(Color::new(0.2, 0.4, 0.8))),
+        ];
+        for i in 0..5 {
+            world.add(Box::new(Sphere::new(
+                Point3::new(-1.2 * f64::from(i), 0.5, -3.0 * f64::from(i)),
+                0.5,
+                Arc::clone(&materials[i as usize % materials.len()]),
+            )));
+        }
+
+        let lookfrom = Point3::new(2.0, 0.8, 2.5);
+        let lookat = Point3::new(-1.5, 0.5, -4.5);
+        let vup = Vec3::new(0.0, 1.0, 0.0);
+        let dist_to_focus = (lookfrom - Point3::new(0.0, 0.5, 0.0)).length();
+
+        let aspect_ratio = 3.0 / 2.0;
+        let image_width: u32 = 600;
+
+        let camera = RealisticCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            LensElement::double_gauss_50mm(),
+            43.3,
+            aspect_ratio,
+            dist_to_focus,
+        )
+        .with_aperture_shape(Aperture::polygon(6, 90.0));
+
+        let mut scene = Scene::new(world, Arc::new(camera), image_width, aspect_ratio);
+        scene.set_background(Arc::new(Gradient::new(
+            Color::new(0.05, 0.04, 0.06),
+            Color::new(0.01, 0.01, 0.03),
+        )));
+        scene.add_light(Arc::new(DirectionalLight::new(
+            Vec3::new(1.0, -0.6, -0.5),
+            Color::new(1.0, 0.8, 0.6),
+        )));
+        let lamp = Arc::new(DiffuseLight::new(Color::new(40.0, 30.0, 15.0)));
+        for x in -4..=4 {
+            for y in 0..3 {
+                scene.add_sphere_light(Sphere::new(
+                    Point3::new(2.5 * f64::from(x), 1.0 + 1.5 * f64::from(y), -40.0),
+                    0.1,
+                    Arc::clone(&lamp) as Arc<dyn Material>,
+                ));
+            }
+        }
+        scene
+    }
+
+    // Ball flying past the spheres of the daylight scene, photographed at ISO 50, 1/25 s
+    // and f/22 with a 50 mm lens, close to the sunny 16 exposure
+    #[allow(dead_code)]
+    pub(crate) fn setup_motion_blur_scene() -> Scene {
+        let mut world = HittableList::default();
+
+        let material_ground = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let material_clay = OrenNayar::new(Color::new(0.8, 0.5, 0.3), 20.0);
+        let material_silver = Conductor::silver(0.05);
+        let material_ball = Lambertian::new(Color::new(0.8, 0.8, 0.1));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Arc::new(material_ground),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(-1.2, 0.5, 0.0),
+            0.5,
+            Arc::new(material_clay),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(1.2, 0.5, 0.0),
+            0.5,
+            Arc::new(material_silver),
+        )));
+
+        // Six meters per second to the right
+        world.add(Box::new(
+            Sphere::new(Point3::new(-0.2, 1.2, 0.5), 0.15, Arc::new(material_ball))
+                .with_velocity(Vec3::new(6.0, 0.0, 0.0)),
+        ));
+
+        let lookfrom = Point3::new(0.0, 1.5, 6.0);
+        let lookat = Point3::new(0.0, 0.6, 0.0);
+        let vup = Vec3::new(0.0, 1.0, 0.0);
+        let dist_to_focus = (lookfrom - lookat).length();
+
+        let aspect_ratio = 16.0 / 9.0;
+        let image_width: u32 = 400;
+
+        let camera = PhysicalCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            50.0,
+            aspect_ratio,
+            dist_to_focus,
+            ExposureSettings::new(50.0, 1.0 / 25.0, 22.0),
+        );
+
+        let mut scene = Scene::new(world, Arc::new(camera), image_width, aspect_ratio);
+        let sky = PreethamSky::new(Vec3::new(-1.0, 0.45, -0.6), 3.0, 0.1);
+        scene.add_light(Arc::new(sky.sun_light(3.0)));
+        scene.set_background(Arc::new(sky));
+        scene
+    }
+
+    // Night version of the random sphere field, where hundreds of the small spheres
+    // glow and light the scene
+    #[allow(dead_code)]
+    pub(crate) fn setup_many_lights_scene() -> Scene {
+        let mut world = HittableList::default();
+        let mut emitters = Vec::new();
+
+        let material_ground = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Arc::new(material_ground),
+        )));
+
+        for a in -11..11 {
+            for b in -11..11 {
+                let center = Point3::new(
+                    a as f64 + 0.9 * random_double(),
+                    0.2,
+                    b as f64 + 0.9 * random_double(),
+                );
+                if (center - Point3::new(4.0, 0.2, 0.0)).length() <= 0.9 {
+                    continue;
+                }
+
+                match random_double() {
+                    mat if mat < 0.5 => {
+                        // Light
+                        let emit = 4.0 * Color::random_in_range(0.2, 1.0);
+                        emitters.push(Sphere::new(center, 0.2, Arc::new(DiffuseLight::new(emit))));
+                    }
+                    _ => {
+                        // Diffuse
+                        let albedo = Color::random() * Color::random();
+                        world.add(Box::new(Sphere::new(
+                            center,
+                            0.2,
+                            Arc::new(Lambertian::new(albedo)),
+                        )));
+                    }
+                }
+            }
+        }
+
+        let material_1 = Dielectric::new(1.5);
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, 1.0, 0.0),
+            1.0,
+            Arc::new(material_1),
+        )));
+
+        let material_2 = Lambertian::new(Color::new(0.4, 0.2, 0.1));
+        world.add(Box::new(Sphere::new(
+            Point3::new(-4.0, 1.0, 0.0),
+            1.0,
+            Arc::new(material_2),
+        )));
+
+        let material_3 = Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
+        world.add(Box::new(Sphere::new(
+            Point3::new(4.0, 1.0, 0.0),
+            1.0,
+            Arc::new(material_3),
+        )));
+
+        let lookfrom = Point3::new(13.0, 2.0, 3.0);
+        let lookat = Point3::new(0.0, 0.0, 0.0);
+        let vup = Vec3::new(0.0, 1.0, 0.0);
+        let dist_to_focus = 10.0;
+        let aperture = 0.1;
+
+        let aspect_ratio = 3.0 / 2.0;
+        let image_width: u32 = 400;
+
+        let camera = PerspectiveCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            20.0,
+            aspect_ratio,
+            aperture,
+            dist_to_focus,
+        );
+
+        let mut scene = Scene::new(world, Arc::new(camera), image_width, aspect_ratio);
+        scene.set_background(Arc::new(Gradient::new(
+            Color::new(0.0, 0.0, 0.0),
+            Color::new(0.01, 0.01, 0.03),
+        )));
+        for emitter in emitters {
+            scene.add_sphere_light(emitter);
+        }
+        scene
+    }
+
+    // The classic Cornell box, lit by a rectangular ceiling light and a small lamp
+    #[allow(dead_code)]
+    pub(crate) fn setup_cornell_box_scene() -> Scene {
+        let mut world = HittableList::default();
+
+        let red: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
+        let white: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+        let green: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15)));
+
+        world.add(Box::new(Quad::new(
+            Point3::new(555.0, 0.0, 0.0),
+            Vec3::new(0.0, 555.0, 0.0),
+            Vec3::new(0.0, 0.0, 555.0),
+            green,
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 555.0, 0.0),
+            Vec3::new(0.0, 0.0, 555.0),
+            red,
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(555.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 555.0),
+            Arc::clone(&white),
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(555.0, 555.0, 555.0),
+            Vec3::new(-555.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -555.0),
+            Arc::clone(&white),
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(0.0, 0.0, 555.0),
+            Vec3::new(555.0, 0.0, 0.0),
+            Vec3::new(0.0, 555.0, 0.0),
+            Arc::clone(&white),
+        )));
+
+        world.add(Box::new(Sphere::new(
+            Point3::new(190.0, 90.0, 190.0),
+            90.0,
+            Arc::new(Dielectric::new(1.5)),
+        )));
+        world.add(Box::new(Sphere::new(
+            Point3::new(370.0, 120.0, 370.0),
+            120.0,
+            Arc::new(Conductor::gold(0.2)),
+        )));
+
+        let lookfrom = Point3::new(278.0, 278.0, -800.0);
+        let lookat = Point3::new(278.0, 278.0, 0.0);
+        let vup = Vec3::new(0.0, 1.0, 0.0);
+        let dist_to_focus = 10.0;
+        let aperture = 0.0;
+
+        let aspect_ratio = 1.0;
+        let image_width: u32 = 400;
+
+        let camera = PerspectiveCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            40.0,
+            aspect_ratio,
+            aperture,
+            dist_to_focus,
+        );
+
+        let mut scene = Scene::new(world, Arc::new(camera), image_width, aspect_ratio);
+        scene.set_background(Arc::new(Gradient::new(
+            Color::new(0.0, 0.0, 0.0),
+            Color::new(0.0, 0.0, 0.0),
+        )));
+        scene.add_quad_light(Quad::new(
+            Point3::new(343.0, 554.0, 332.0),
+            Vec3::new(-130.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -105.0),
+            Arc::new(DiffuseLight::new(Color::new(15.0, 15.0, 15.0))),
+        ));
+        scene.add_sphere_light(Sphere::new(
+            Point3::new(100.0, 400.0, 300.0),
+            20.0,
+            Arc::new(DiffuseLight::new(Color::new(8.0, 6.0, 3.0))),
+        ));
+        scene
+    }
+}
@@ -0,0 +1,86 @@
+use std::ops;
+
+// Minimal complex number type, used for the Fresnel equations of conductors.
+#[derive(Clone, Copy, Default)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub const fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    pub const fn real(re: f64) -> Complex {
+        Complex { re, im: 0.0 }
+    }
+
+    // Squared magnitude
+    pub fn norm(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    // e^(re + i*im)
+    pub fn exp(&self) -> Complex {
+        let scale = self.re.exp();
+        Complex::new(scale * self.im.cos(), scale * self.im.sin())
+    }
+
+    // Principal square root
+    pub fn sqrt(&self) -> Complex {
+        let n = self.norm().sqrt();
+        if n == 0.0 {
+            return Complex::real(0.0);
+        }
+        let t1 = (0.5 * (n + self.re.abs())).sqrt();
+        let t2 = 0.5 * self.im / t1;
+        if self.re >= 0.0 {
+            Complex::new(t1, t2)
+        } else {
+            Complex::new(t2.abs(), t1.copysign(self.im))
+        }
+    }
+}
+
+impl ops::Add for Complex {
+    type Output = Self;
+    fn add(self, other: Self) -> Self::Output {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl ops::Sub for Complex {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self::Output {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl ops::Mul for Complex {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self::Output {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl ops::Mul<Complex> for f64 {
+    type Output = Complex;
+    fn mul(self, c: Complex) -> Complex {
+        Complex::new(self * c.re, self * c.im)
+    }
+}
+
+impl ops::Div for Complex {
+    type Output = Self;
+    fn div(self, other: Self) -> Self::Output {
+        let scale = 1.0 / other.norm();
+        Complex::new(
+            scale * (self.re * other.re + self.im * other.im),
+            scale * (self.im * other.re - self.re * other.im),
+        )
+    }
+}
@@ -0,0 +1,169 @@
+// Piecewise constant 1D distribution over [0,1), sampled by inverting its CDF.
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    func_int: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].abs() / n as f64;
+        }
+        let func_int = cdf[n];
+        for (i, value) in cdf.iter_mut().enumerate().skip(1) {
+            // An all zero function falls back to a uniform distribution
+            *value = match func_int > 0.0 {
+                true => *value / func_int,
+                false => i as f64 / n as f64,
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    // Returns the sampled position in [0,1), its density and the segment it fell into
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        // Last segment whose CDF start is <= u
+        let offset = match self.cdf.partition_point(|c| *c <= u) {
+            0 => 0,
+            i => (i - 1).min(self.func.len() - 1),
+        };
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = match width > 0.0 {
+            true => (u - self.cdf[offset]) / width,
+            false => 0.0,
+        };
+        let x = (offset as f64 + du) / self.func.len() as f64;
+        (x, self.pdf_at(offset), offset)
+    }
+
+    fn pdf_at(&self, offset: usize) -> f64 {
+        match self.func_int > 0.0 {
+            true => self.func[offset].abs() / self.func_int,
+            false => 1.0,
+        }
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let offset = ((x * self.func.len() as f64) as usize).min(self.func.len() - 1);
+        self.pdf_at(offset)
+    }
+}
+
+// Piecewise constant 2D distribution over [0,1)^2, given as rows of v and columns of u.
+// Samples v from the marginal distribution of the rows and then u from the chosen row.
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], width: usize, height: usize) -> Distribution2D {
+        let conditional: Vec<Distribution1D> = func
+            .chunks_exact(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.func_int).collect());
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    // Returns (u, v) in [0,1)^2 and the density with respect to that unit square
+    pub fn sample_continuous(&self, u1: f64, u2: f64) -> (f64, f64, f64) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u2);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u1);
+        (u, v, pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let row = ((v * self.conditional.len() as f64) as usize).min(self.conditional.len() - 1);
+        self.conditional[row].pdf(u) * self.marginal.pdf(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampled_histogram_matches_the_pdf() {
+        let func = vec![1.0, 0.0, 3.0, 0.5, 2.5];
+        let distribution = Distribution1D::new(func.clone());
+
+        // Stratified inputs make the histogram exact up to the stratum width
+        let samples = 100_000;
+        let mut histogram = vec![0.0; func.len()];
+        for i in 0..samples {
+            let (x, pdf, offset) =
+                distribution.sample_continuous((i as f64 + 0.5) / samples as f64);
+            assert!((0.0..1.0).contains(&x));
+            assert_eq!(offset, (x * func.len() as f64) as usize);
+            assert!((pdf - distribution.pdf(x)).abs() < 1e-12);
+            histogram[offset] += 1.0 / samples as f64;
+        }
+        for (offset, frequency) in histogram.iter().enumerate() {
+            let center = (offset as f64 + 0.5) / func.len() as f64;
+            let expected = distribution.pdf(center) / func.len() as f64;
+            assert!(
+                (frequency - expected).abs() < 1e-4,
+                "{} {}",
+                offset,
+                frequency
+            );
+        }
+        assert_eq!(histogram[1], 0.0);
+    }
+
+    #[test]
+    fn constant_and_zero_functions_are_uniform() {
+        for func in &[vec![2.0; 8], vec![0.0; 8]] {
+            let distribution = Distribution1D::new(func.clone());
+            for &u in &[0.0, 0.3, 0.77, 0.999] {
+                let (x, pdf, _) = distribution.sample_continuous(u);
+                assert!((x - u).abs() < 1e-12);
+                assert!((pdf - 1.0).abs() < 1e-12);
+            }
+        }
+
+        let distribution = Distribution2D::new(&[0.7; 12], 4, 3);
+        for &(u1, u2) in &[(0.1, 0.9), (0.5, 0.5), (0.95, 0.2)] {
+            let (u, v, pdf) = distribution.sample_continuous(u1, u2);
+            assert!((u - u1).abs() < 1e-12 && (v - u2).abs() < 1e-12);
+            assert!((pdf - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn sampled_2d_histogram_matches_the_pdf() {
+        let (width, height) = (4, 3);
+        let func = [1.0, 2.0, 0.0, 1.0, 0.5, 0.5, 4.0, 1.0, 3.0, 0.0, 1.0, 2.0];
+        let distribution = Distribution2D::new(&func, width, height);
+
+        let n = 400;
+        let mut histogram = vec![0.0; width * height];
+        for i in 0..n {
+            for j in 0..n {
+                let u1 = (i as f64 + 0.5) / n as f64;
+                let u2 = (j as f64 + 0.5) / n as f64;
+                let (u, v, pdf) = distribution.sample_continuous(u1, u2);
+                assert!((pdf - distribution.pdf(u, v)).abs() < 1e-12);
+                let cell = (v * height as f64) as usize * width + (u * width as f64) as usize;
+                histogram[cell] += 1.0 / (n * n) as f64;
+            }
+        }
+
+        let total: f64 = func.iter().sum();
+        for (cell, frequency) in histogram.iter().enumerate() {
+            assert!((frequency - func[cell] / total).abs() < 1e-3, "{}", cell);
+        }
+    }
+}
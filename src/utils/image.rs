@@ -0,0 +1,230 @@
+use crate::vec::vec3::Color;
+use std::fs;
+use std::io;
+
+// In-memory RGB image with linear values in [0,1], row 0 at the top.
+pub struct Image {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) pixels: Vec<Color>,
+}
+
+impl Image {
+    // Reads a binary (P6) or plain (P3) PPM file, the format the renderer writes itself.
+    // With `srgb` the 2.2 gamma of color images is removed; leave it off for data such
+    // as normal or height maps.
+    pub fn load_ppm(path: &str, srgb: bool) -> io::Result<Image> {
+        let bytes = fs::read(path)?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        // Header: magic, width, height and max value, separated by whitespace and comments
+        let mut pos = 0;
+        let mut header = Vec::new();
+        while header.len() < 4 {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < bytes.len() && bytes[pos] == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            let start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid("Truncated PPM header"));
+            }
+            header.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+        }
+        // A single whitespace character separates the header from binary data
+        pos += 1;
+
+        let parse = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| invalid("Invalid PPM header"))
+        };
+        let width = parse(&header[1])?;
+        let height = parse(&header[2])?;
+        let max_value = parse(&header[3])? as f64;
+
+        let samples: Vec<f64> = match header[0].as_str() {
+            "P6" if max_value < 256.0 => bytes[pos.min(bytes.len())..]
+                .iter()
+                .map(|b| f64::from(*b))
+                .collect(),
+            "P6" => bytes[pos.min(bytes.len())..]
+                .chunks_exact(2)
+                .map(|b| f64::from(u16::from_be_bytes([b[0], b[1]])))
+                .collect(),
+            "P3" => String::from_utf8_lossy(&bytes[pos.min(bytes.len())..])
+                .split_ascii_whitespace()
+                .map(|s| s.parse::<f64>().map_err(|_| invalid("Invalid PPM sample")))
+                .collect::<io::Result<Vec<f64>>>()?,
+            _ => return Err(invalid("Only P3 and P6 PPM files are supported")),
+        };
+        if samples.len() < width * height * 3 {
+            return Err(invalid("Truncated PPM data"));
+        }
+
+        let decode = |x: f64| {
+            let x = x / max_value;
+            match srgb {
+                true => x.powf(2.2),
+                false => x,
+            }
+        };
+        let pixels = samples
+            .chunks_exact(3)
+            .take(width * height)
+            .map(|c| Color::new(decode(c[0]), decode(c[1]), decode(c[2])))
+            .collect();
+
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    // Reads a Radiance RGBE (.hdr) file, either flat or with the usual per-channel
+    // run-length encoding. Values are linear radiance and may be well above one.
+    pub fn load_hdr(path: &str) -> io::Result<Image> {
+        let bytes = fs::read(path)?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        // Header lines end at an empty line, followed by the resolution line
+        let mut pos = 0;
+        let mut next_line = || {
+            let start = pos;
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+                pos += 1;
+            }
+            pos += 1;
+            String::from_utf8_lossy(&bytes[start..(pos - 1).min(bytes.len())]).to_string()
+        };
+        let magic = next_line();
+        if !magic.starts_with("#?") {
+            return Err(invalid("Not a Radiance HDR file"));
+        }
+        loop {
+            let line = next_line();
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+                return Err(invalid("Only RGBE HDR files are supported"));
+            }
+        }
+        let resolution = next_line();
+        let fields: Vec<&str> = resolution.split_ascii_whitespace().collect();
+        if fields.len() != 4 || fields[0] != "-Y" || fields[2] != "+X" {
+            return Err(invalid("Only -Y H +X W HDR orientations are supported"));
+        }
+        let parse = |s: &str| s.parse::<usize>().map_err(|_| invalid("Invalid HDR size"));
+        let height = parse(fields[1])?;
+        let width = parse(fields[3])?;
+
+        let mut pixels = Vec::with_capacity(width * height);
+        let mut scanline = vec![[0u8; 4]; width];
+        for _ in 0..height {
+            let rle = (8..0x8000).contains(&width)
+                && pos + 4 <= bytes.len()
+                && bytes[pos] == 2
+                && bytes[pos + 1] == 2
+                && bytes[pos + 2] & 0x80 == 0;
+            if rle {
+                if ((bytes[pos + 2] as usize) << 8 | bytes[pos + 3] as usize) != width {
+                    return Err(invalid("HDR scanline width mismatch"));
+                }
+                pos += 4;
+                // Each of the four channels is run-length encoded separately
+                for channel in 0..4 {
+                    let mut x = 0;
+                    while x < width {
+                        let count = *bytes
+                            .get(pos)
+                            .ok_or_else(|| invalid("Truncated HDR data"))?;
+                        pos += 1;
+                        if count > 128 {
+                            let run = (count - 128) as usize;
+                            let value = *bytes
+                                .get(pos)
+                                .ok_or_else(|| invalid("Truncated HDR data"))?;
+                            pos += 1;
+                            if x + run > width {
+                                return Err(invalid("Invalid HDR run length"));
+                            }
+                            for texel in &mut scanline[x..x + run] {
+                                texel[channel] = value;
+                            }
+                            x += run;
+                        } else {
+                            let run = count as usize;
+                            if run == 0 || x + run > width || pos + run > bytes.len() {
+                                return Err(invalid("Invalid HDR run length"));
+                            }
+                            for (texel, value) in scanline[x..x + run].iter_mut().zip(&bytes[pos..])
+                            {
+                                texel[channel] = *value;
+                            }
+                            pos += run;
+                            x += run;
+                        }
+                    }
+                }
+            } else {
+                if pos + 4 * width > bytes.len() {
+                    return Err(invalid("Truncated HDR data"));
+                }
+                for (texel, rgbe) in scanline.iter_mut().zip(bytes[pos..].chunks_exact(4)) {
+                    texel.copy_from_slice(rgbe);
+                }
+                pos += 4 * width;
+            }
+
+            pixels.extend(scanline.iter().map(|[r, g, b, e]| match e {
+                0 => Color::new(0.0, 0.0, 0.0),
+                _ => {
+                    let scale = 2f64.powi(i32::from(*e) - 136);
+                    Color::new(
+                        (f64::from(*r) + 0.5) * scale,
+                        (f64::from(*g) + 0.5) * scale,
+                        (f64::from(*b) + 0.5) * scale,
+                    )
+                }
+            }));
+        }
+
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    // Reads the first RGBA layer of an OpenEXR file, ignoring alpha.
+    pub fn load_exr(path: &str) -> io::Result<Image> {
+        let to_io_error = |e: exr::error::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+        let image = exr::prelude::read_first_rgba_layer_from_file(
+            path,
+            |resolution, _| Image {
+                width: resolution.width(),
+                height: resolution.height(),
+                pixels: vec![Color::new(0.0, 0.0, 0.0); resolution.width() * resolution.height()],
+            },
+            |image: &mut Image, position, (r, g, b, _a): (f32, f32, f32, f32)| {
+                let idx = position.y() * image.width + position.x();
+                image.pixels[idx] = Color::new(f64::from(r), f64::from(g), f64::from(b));
+            },
+        )
+        .map_err(to_io_error)?;
+        Ok(image.layer_data.channel_data.pixels)
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+    }
+}
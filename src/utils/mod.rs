@@ -1,2 +1,5 @@
+pub mod complex;
+pub mod distribution;
+pub mod image;
 pub mod math_constants;
 pub mod util;
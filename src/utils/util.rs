@@ -33,6 +33,17 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     return x;
 }
 
+// Power heuristic (beta = 2) weight for a sample of the strategy with density `f_pdf`,
+// when `g_pdf` is the density of the other strategy for the same direction.
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let f = f_pdf * f_pdf;
+    let g = g_pdf * g_pdf;
+    match f + g > 0.0 {
+        true => f / (f + g),
+        false => 0.0,
+    }
+}
+
 // Returns a random real in [0,1).
 pub fn random_double() -> f64 {
     let mut rng = rand::thread_rng();
@@ -45,6 +56,20 @@ pub fn random_double_in_range(min: f64, max: f64) -> f64 {
     return rng.gen_range(min, max);
 }
 
+// Hashes the given values to a real in [0,1), the same one every time for equal inputs.
+pub fn hash_double(values: &[f64]) -> f64 {
+    let mut hash: u64 = 0x9e37_79b9_7f4a_7c15;
+    for value in values {
+        // SplitMix64 finalizer over the running hash and the bits of each value
+        hash ^= value.to_bits();
+        hash = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^= hash >> 31;
+    }
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
 pub fn write_pixels_to_file(pixel_vec: &mut Vec<String>, image_width: u32, image_height: u32) {
     let mut file = std::fs::File::create("image/image.ppm").expect("Create failed");
     file.write_all(format!("P3\n{} {}\n255\n", image_width, image_height).as_bytes())
@@ -62,6 +62,15 @@ impl Vec3 {
         r_out_perp + r_out_parallel
     }
 
+    // Cosine weighted direction on the hemisphere around +z
+    pub fn random_cosine_direction() -> Vec3 {
+        let r1 = random_double();
+        let r2 = random_double();
+        let phi = 2.0 * PI * r1;
+        let z = (1.0 - r2).sqrt();
+        Vec3::new(phi.cos() * r2.sqrt(), phi.sin() * r2.sqrt(), z)
+    }
+
     pub fn random_in_unit_disk() -> Vec3 {
         loop {
             let p = Vec3::new(
@@ -208,6 +217,11 @@ impl Color {
     pub fn b(&self) -> f64 {
         self.e[2]
     }
+
+    // Relative luminance of linear sRGB
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.e[0] + 0.7152 * self.e[1] + 0.0722 * self.e[2]
+    }
 }
 
 impl std::fmt::Display for Color {
@@ -238,6 +252,7 @@ impl Point3 {
 pub struct Ray {
     pub origin: Point3, // Origin
     pub dir: Vec3,      // Direction
+    pub time: f64,      // Seconds since the shutter opened
 }
 
 impl Ray {
@@ -245,6 +260,16 @@ impl Ray {
         Ray {
             origin,
             dir: direction,
+            time: 0.0,
+        }
+    }
+
+    // Ray leaving at `time`, for rays continuing the path of an earlier one
+    pub fn new_at(origin: Point3, direction: Vec3, time: f64) -> Ray {
+        Ray {
+            origin,
+            dir: direction,
+            time,
         }
     }
 
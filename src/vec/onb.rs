@@ -0,0 +1,43 @@
+use crate::vec::vec3::Vec3;
+
+// Orthonormal basis, used to move directions in and out of a local shading frame
+// where `w` is the surface normal (the local z axis).
+#[derive(Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn build_from_w(n: &Vec3) -> Onb {
+        let w = n.unit();
+        let a = match w.x().abs() > 0.9 {
+            true => Vec3::new(0.0, 1.0, 0.0),
+            false => Vec3::new(1.0, 0.0, 0.0),
+        };
+        let v = w.cross(a).unit();
+        let u = w.cross(v);
+        Onb { u, v, w }
+    }
+
+    // Basis around `n` with `u` aligned to the given tangent, e.g. dp/du of a surface
+    pub fn build_from_w_and_u(n: &Vec3, tangent: &Vec3) -> Onb {
+        let w = n.unit();
+        let projected = *tangent - tangent.dot(&w) * w;
+        if projected.length_squared() < 1e-12 {
+            return Onb::build_from_w(n);
+        }
+        let u = projected.unit();
+        let v = w.cross(u);
+        Onb { u, v, w }
+    }
+
+    pub fn local_to_world(&self, a: &Vec3) -> Vec3 {
+        a.x() * self.u + a.y() * self.v + a.z() * self.w
+    }
+
+    pub fn world_to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+}